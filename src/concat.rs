@@ -0,0 +1,191 @@
+//! Concatenation of adjacent string literals (translation phase 6).
+
+use crate::dialect::Dialect;
+use crate::literal::{decode_literal_as, DataModel, LiteralError, LiteralUnits};
+use crate::token::*;
+use std::iter::Peekable;
+use std::ops::Range;
+
+/// A token, or a string literal made of one or more adjacent ones.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Concatenated<'a> {
+    Token(Token<'a>),
+    StrLit(StringLiteral<'a>),
+}
+
+/// Adjacent string literal tokens, forming a single string literal.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct StringLiteral<'a> {
+    /// The `StrLit` tokens in source order, never empty
+    pub parts: Vec<Token<'a>>,
+}
+
+impl<'a> StringLiteral<'a> {
+    /// Returns the byte range from the first part to the last one.
+    pub fn byte_range(&self) -> Range<usize> {
+        self.parts[0].byte_range.start..self.parts[self.parts.len() - 1].byte_range.end
+    }
+
+    /// Returns the char range from the first part to the last one.
+    pub fn char_range(&self) -> Range<usize> {
+        self.parts[0].char_range.start..self.parts[self.parts.len() - 1].char_range.end
+    }
+
+    /// Returns the encoding of the whole literal, which is that of any part
+    /// with a prefix (Section 6.4.5).
+    ///
+    /// Parts with different prefixes, such as `u8"a" L"b"`, cannot be
+    /// concatenated.
+    pub fn encoding(&self) -> Result<Encoding, LiteralError> {
+        let mut encoding = Encoding::Plain;
+        for part in &self.parts {
+            match (encoding, part.kind) {
+                (_, StrLit(Encoding::Plain)) => (),
+                (Encoding::Plain, StrLit(part_encoding)) => encoding = part_encoding,
+                (_, StrLit(part_encoding)) if part_encoding == encoding => (),
+                (_, StrLit(_)) => return Err(LiteralError::IncompatibleEncodings),
+                _ => return Err(LiteralError::InvalidLiteral),
+            }
+        }
+        Ok(encoding)
+    }
+
+    /// Decodes the parts in the encoding of the whole literal, and
+    /// concatenates their code units.
+    ///
+    /// The ranges of unknown escape sequences are byte ranges in the
+    /// source of the parts, like [`byte_range`](StringLiteral::byte_range).
+    pub fn decode(&self, dialect: Dialect, model: DataModel) -> Result<LiteralUnits, LiteralError> {
+        let encoding = self.encoding()?;
+        let mut literal: Option<LiteralUnits> = None;
+        for part in &self.parts {
+            let mut units = decode_literal_as(&part.spelling(), encoding, dialect, model)?;
+            for escape in &mut units.unknown_escapes {
+                *escape = part.source_ranges(escape.clone()).0;
+            }
+            match &mut literal {
+                Some(literal) => {
+                    literal.units.append(&mut units.units);
+                    literal.unknown_escapes.append(&mut units.unknown_escapes);
+                }
+                None => literal = Some(units),
+            }
+        }
+        Ok(literal.unwrap())
+    }
+}
+
+/// An iterator joining adjacent string literals; see [`concat_strings`].
+pub struct ConcatStrings<'a, I: Iterator<Item = Token<'a>>> {
+    tokens: Peekable<I>,
+}
+
+/// Groups adjacent `StrLit` tokens of `tokens` into [`StringLiteral`]s,
+/// passing every other token through.
+///
+/// The tokens are expected without trivia, as returned by
+/// [`Lexer::iter`](crate::Lexer::iter).
+pub fn concat_strings<'a, I>(tokens: I) -> ConcatStrings<'a, I::IntoIter>
+where
+    I: IntoIterator<Item = Token<'a>>,
+{
+    ConcatStrings {
+        tokens: tokens.into_iter().peekable(),
+    }
+}
+
+impl<'a, I: Iterator<Item = Token<'a>>> Iterator for ConcatStrings<'a, I> {
+    type Item = Concatenated<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let token = self.tokens.next()?;
+        if !matches!(token.kind, StrLit(_)) {
+            return Some(Concatenated::Token(token));
+        }
+        let mut parts = vec![token];
+        while let Some(token) = self.tokens.next_if(|token| matches!(token.kind, StrLit(_))) {
+            parts.push(token);
+        }
+        Some(Concatenated::StrLit(StringLiteral { parts }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::Lexer;
+
+    fn concat(src: &str) -> Vec<Concatenated<'_>> {
+        concat_strings(Lexer::new(src).with_dialect(Dialect::C11).iter()).collect()
+    }
+
+    fn string_literal(src: &str) -> StringLiteral<'_> {
+        match concat(src).into_iter().next() {
+            Some(Concatenated::StrLit(literal)) => literal,
+            _ => panic!("not a string literal"),
+        }
+    }
+
+    #[test]
+    fn test_concat_strings() {
+        let items = concat(r#"puts("Hello, " /* comment */ "world" "!\n");"#);
+        assert_eq!(items.len(), 5);
+        assert_debug_snapshot!(
+            items[2],
+            r#"
+StrLit(
+    StringLiteral {
+        parts: [
+            [StrLit(Plain)@5..14],
+            [StrLit(Plain)@29..36],
+            [StrLit(Plain)@37..42],
+        ],
+    },
+)"#
+        );
+
+        let literal = string_literal(r#""Hello, " /* comment */ "world" "!\n""#);
+        assert_eq!(literal.byte_range(), 0..37);
+        let units = literal.decode(Dialect::C11, DataModel::LP64).unwrap().units;
+        let expected: Vec<u32> = "Hello, world!\n".bytes().map(u32::from).collect();
+        assert_eq!(units, expected);
+    }
+
+    #[test]
+    fn test_concat_encodings() {
+        let literal = string_literal(r#""a" L"b" "\xff""#);
+        assert_eq!(literal.encoding(), Ok(Encoding::Wide));
+        let literal = literal.decode(Dialect::C11, DataModel::LP64).unwrap();
+        assert_eq!(literal.unit_width, 32);
+        assert_eq!(literal.units, vec![0x61, 0x62, 0xff]);
+
+        let literal = string_literal(r#"u"\U0001F600" u"!""#);
+        assert_eq!(
+            literal
+                .decode(Dialect::C11, DataModel::LP64)
+                .map(|literal| literal.units),
+            Ok(vec![0xd83d, 0xde00, 0x21])
+        );
+
+        let literal = string_literal(r#"u8"a" "b" L"c""#);
+        assert_eq!(literal.encoding(), Err(LiteralError::IncompatibleEncodings));
+        assert_eq!(
+            literal.decode(Dialect::C11, DataModel::LP64),
+            Err(LiteralError::IncompatibleEncodings)
+        );
+    }
+
+    #[test]
+    fn test_concat_unknown_escapes() {
+        let literal = string_literal(r#""\q" "a\e""#);
+        let literal = literal.decode(Dialect::C11, DataModel::LP64).unwrap();
+        assert_eq!(literal.unknown_escapes, vec![1..3, 7..9]);
+
+        // The ranges skip line splices and start at the first part.
+        let literal = match &concat("x = \"a\" \"\\\n\\q\";")[2] {
+            Concatenated::StrLit(literal) => literal.decode(Dialect::C11, DataModel::LP64),
+            _ => panic!("not a string literal"),
+        };
+        assert_eq!(literal.unwrap().unknown_escapes, vec![11..13]);
+    }
+}
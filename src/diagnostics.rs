@@ -0,0 +1,675 @@
+//! Diagnostics and how they are reported.
+//!
+//! The lexer and the preprocessor describe every problem they find as a
+//! [`Diagnostic`], which an [`Emitter`] then renders, collects or filters.
+
+use crate::locale;
+use crate::source::SourceFile;
+use crate::token::*;
+use anyhow::Result;
+use std::fmt;
+use std::ops::Range;
+use termcolor::{
+    Color::{self, Blue, Cyan, Green, Red, White, Yellow},
+    ColorChoice, ColorSpec, StandardStream, WriteColor,
+};
+use unicode_width::UnicodeWidthChar;
+
+/// How serious a diagnostic is.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Severity {
+    /// Additional information attached to another diagnostic
+    Note,
+    Warning,
+    Error,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let key = match self {
+            Severity::Note => "severity.note",
+            Severity::Warning => "severity.warning",
+            Severity::Error => "severity.error",
+        };
+        f.write_str(&locale::message(key, &[]))
+    }
+}
+
+/// A span of the source text with a message about it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Label {
+    /// Byte offsets of the labelled text
+    pub byte_range: Range<usize>,
+    /// Char offsets of the labelled text
+    pub char_range: Range<usize>,
+    /// What to print next to the text, which may be empty
+    pub message: String,
+}
+
+impl Label {
+    pub fn new(byte_range: Range<usize>, char_range: Range<usize>, message: String) -> Self {
+        Label {
+            byte_range,
+            char_range,
+            message,
+        }
+    }
+}
+
+/// A problem found in a source file.
+///
+/// The spans of a diagnostic are offsets into a single source, which is
+/// given to the [`Emitter`] along with it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    /// A stable identifier of the problem, such as `E0002`, if it has one
+    pub code: Option<&'static str>,
+    /// What went wrong, in a single line
+    pub message: String,
+    /// The text the problem is about
+    pub primary: Label,
+    /// Other text related to the problem
+    pub secondary: Vec<Label>,
+    /// Remarks printed after the source text
+    pub notes: Vec<String>,
+    /// A suggestion to fix the problem
+    pub help: Option<String>,
+}
+
+impl Diagnostic {
+    /// Creates a diagnostic about `primary`, without code, notes or help.
+    pub fn new(severity: Severity, message: String, primary: Label) -> Self {
+        Diagnostic {
+            severity,
+            code: None,
+            message,
+            primary,
+            secondary: Vec::new(),
+            notes: Vec::new(),
+            help: None,
+        }
+    }
+
+    /// Returns the diagnostic of an error token, pointing at its
+    /// [offending text](Token::error_range), or `None` for other tokens.
+    pub fn from_token(token: &Token) -> Option<Self> {
+        let kind = match token.kind {
+            Error(kind) => kind,
+            _ => return None,
+        };
+        let (byte_range, char_range) = token.error_range();
+        let primary = Label::new(byte_range, char_range, String::new());
+        let diagnostic = Diagnostic::new(Severity::Error, kind.message(), primary);
+        Some(diagnostic.with_code(kind.code()).with_note(kind.cause()))
+    }
+
+    /// Returns the diagnostic of a lexer warning about the given text.
+    pub fn from_warning(
+        kind: WarningKind,
+        byte_range: Range<usize>,
+        char_range: Range<usize>,
+    ) -> Self {
+        let primary = Label::new(byte_range, char_range, String::new());
+        let diagnostic = Diagnostic::new(Severity::Warning, kind.message(), primary);
+        diagnostic.with_code(kind.code()).with_note(kind.cause())
+    }
+
+    pub fn with_code(mut self, code: &'static str) -> Self {
+        self.code = Some(code);
+        self
+    }
+
+    pub fn with_secondary(mut self, label: Label) -> Self {
+        self.secondary.push(label);
+        self
+    }
+
+    pub fn with_note(mut self, note: String) -> Self {
+        self.notes.push(note);
+        self
+    }
+
+    pub fn with_help(mut self, help: String) -> Self {
+        self.help = Some(help);
+        self
+    }
+
+    /// Returns `true` for errors, as opposed to warnings and notes.
+    pub fn is_error(&self) -> bool {
+        self.severity == Severity::Error
+    }
+}
+
+macro_rules! explanations {
+    ($($code:literal,)*) => {
+        /// Returns the long-form description of the problem with the given
+        /// code, with examples of it and its fix, or `None` if it has none.
+        pub fn explain(code: &str) -> Option<&'static str> {
+            match code {
+                $($code => Some(include_str!(concat!("explanations/", $code, ".md"))),)*
+                _ => None,
+            }
+        }
+    };
+}
+
+explanations! {
+    "E0001", "E0002", "E0003", "E0004", "E0005", "E0006", "E0007", "E0008", "E0009", "E0010",
+    "E0011", "E0012", "E0013", "E0014", "E0015", "E0016", "E0017", "E0018", "E0019", "E0020",
+    "E0021",
+}
+
+/// Something that reports diagnostics, e.g. by printing them.
+pub trait Emitter {
+    /// Reports `diagnostic`, whose spans are offsets into `source`.
+    fn emit(&mut self, source: &SourceFile, diagnostic: &Diagnostic) -> Result<()>;
+}
+
+/// Collects diagnostics, leaving the sources behind.
+impl Emitter for Vec<Diagnostic> {
+    fn emit(&mut self, _: &SourceFile, diagnostic: &Diagnostic) -> Result<()> {
+        self.push(diagnostic.clone());
+        Ok(())
+    }
+}
+
+/// Prints diagnostics for humans, underlining the text they are about:
+///
+/// ```text
+/// main.c:1:9: error[E0002]: missing terminating '"' character
+///   |
+/// 1 | int s = "abc;
+///   |         ^^^^^
+///   |
+///   = note: a string literal ends on the line it starts on, ...
+/// ```
+pub struct HumanEmitter<W> {
+    writer: W,
+    physical: bool,
+}
+
+impl HumanEmitter<StandardStream> {
+    /// Creates an emitter printing to stderr, in color if it is a terminal.
+    pub fn stderr() -> Self {
+        HumanEmitter::new(StandardStream::stderr(ColorChoice::Auto))
+    }
+}
+
+impl<W: WriteColor> HumanEmitter<W> {
+    pub fn new(writer: W) -> Self {
+        HumanEmitter {
+            writer,
+            physical: false,
+        }
+    }
+
+    /// Reports physical locations rather than the ones set by `#line`
+    /// directives, which is the default.
+    pub fn with_physical(mut self, physical: bool) -> Self {
+        self.physical = physical;
+        self
+    }
+
+    /// Consumes the emitter, returning what it has written to.
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+
+    /// Prints the lines of `label` after a gutter `gutter` columns wide
+    /// holding their numbers, and marks the labelled text.
+    ///
+    /// Text within a line is underlined, while text running over several
+    /// lines is marked where it starts and where it ends:
+    ///
+    /// ```text
+    /// 3 |   int a; /* unclosed
+    ///   |  ________^
+    /// 4 | | int b;
+    ///   | |______^
+    /// ```
+    fn display_label(
+        &mut self,
+        source: &SourceFile,
+        label: &Label,
+        color: Color,
+        gutter: usize,
+    ) -> Result<()> {
+        let ((start_line, start), (end_line, end)) = label_lines(source, label);
+        let mut line = DisplayLine::new(&source.get_line(start_line));
+        let first = line.column(start);
+        if start_line == end_line {
+            let last = line.column(end);
+            line.focus(first..last);
+            self.display_gutter(Some(self.line_number(source, start_line)), gutter)?;
+            writeln!(&mut self.writer, " {}", line)?;
+            self.display_gutter(None, gutter)?;
+            let (first, last) = (line.offset(first), line.offset(last));
+            write!(&mut self.writer, " {: <1$}", "", first)?;
+            let marker = "^".repeat((last - first).max(1));
+            self.display_marker(&marker, &label.message, color)?;
+            writeln!(&mut self.writer)?;
+            return Ok(());
+        }
+
+        line.focus(first..first + 1);
+        self.display_gutter(Some(self.line_number(source, start_line)), gutter)?;
+        writeln!(&mut self.writer, "   {}", line)?;
+        self.display_gutter(None, gutter)?;
+        let marker = format!("{}^", "_".repeat(line.offset(first) + 1));
+        write!(&mut self.writer, "  ")?;
+        self.display_marker(&marker, "", color)?;
+        writeln!(&mut self.writer)?;
+
+        let inner = start_line + 1..end_line;
+        let elided = inner.len() > MAX_INNER_LINES;
+        let shown = if elided { 1 } else { inner.len() };
+        for number in inner.take(shown) {
+            let mut line = DisplayLine::new(&source.get_line(number));
+            line.focus(0..0);
+            self.display_gutter(Some(self.line_number(source, number)), gutter)?;
+            write!(&mut self.writer, " ")?;
+            self.display_marker("|", "", color)?;
+            writeln!(&mut self.writer, " {}", line)?;
+        }
+        if elided {
+            writeln!(&mut self.writer, "...")?;
+        }
+
+        let mut line = DisplayLine::new(&source.get_line(end_line));
+        let last = line.column(end - 1);
+        line.focus(last..last + 1);
+        self.display_gutter(Some(self.line_number(source, end_line)), gutter)?;
+        write!(&mut self.writer, " ")?;
+        self.display_marker("|", "", color)?;
+        writeln!(&mut self.writer, " {}", line)?;
+        self.display_gutter(None, gutter)?;
+        write!(&mut self.writer, " ")?;
+        let marker = format!("|{}^", "_".repeat(line.offset(last) + 1));
+        self.display_marker(&marker, &label.message, color)?;
+        writeln!(&mut self.writer)?;
+        Ok(())
+    }
+
+    /// Prints `number` right-aligned in the gutter, or an empty gutter.
+    fn display_gutter(&mut self, number: Option<usize>, gutter: usize) -> Result<()> {
+        self.writer
+            .set_color(ColorSpec::new().set_fg(Some(Blue)).set_bold(true))?;
+        match number {
+            Some(number) => write!(&mut self.writer, "{:>1$} |", number, gutter)?,
+            None => write!(&mut self.writer, "{: >1$} |", "", gutter)?,
+        }
+        self.writer.reset()?;
+        Ok(())
+    }
+
+    /// Prints `marker` in `color`, followed by `message` if it is not empty.
+    fn display_marker(&mut self, marker: &str, message: &str, color: Color) -> Result<()> {
+        self.writer
+            .set_color(ColorSpec::new().set_fg(Some(color)).set_bold(true))?;
+        write!(&mut self.writer, "{}", marker)?;
+        if !message.is_empty() {
+            write!(&mut self.writer, " {}", message)?;
+        }
+        self.writer.reset()?;
+        Ok(())
+    }
+
+    /// Returns the number shown for the `line`-th line of `source`, which
+    /// is set by `#line` directives unless the emitter is physical.
+    fn line_number(&self, source: &SourceFile, line: usize) -> usize {
+        match self.physical {
+            true => line + 1,
+            false => source.lookup_presumed(source.lines[line]).line + 1,
+        }
+    }
+}
+
+impl<W: WriteColor> Emitter for HumanEmitter<W> {
+    fn emit(&mut self, source: &SourceFile, diagnostic: &Diagnostic) -> Result<()> {
+        let (color, label_color) = match diagnostic.severity {
+            Severity::Error => (Red, Green),
+            Severity::Warning => (Yellow, Yellow),
+            Severity::Note => (Cyan, Cyan),
+        };
+        let position = source.position(diagnostic.primary.char_range.start, self.physical);
+        let bold = |color| ColorSpec::new().set_fg(Some(color)).set_bold(true).clone();
+
+        self.writer.set_color(&bold(White))?;
+        write!(&mut self.writer, "{}: ", position)?;
+        self.writer.set_color(&bold(color))?;
+        match diagnostic.code {
+            Some(code) => write!(&mut self.writer, "{}[{}]: ", diagnostic.severity, code)?,
+            None => write!(&mut self.writer, "{}: ", diagnostic.severity)?,
+        }
+        self.writer.set_color(&bold(White))?;
+        writeln!(&mut self.writer, "{}", diagnostic.message)?;
+        self.writer.reset()?;
+
+        let labels = std::iter::once(&diagnostic.primary).chain(&diagnostic.secondary);
+        let gutter = labels
+            .map(|label| {
+                let (_, (end_line, _)) = label_lines(source, label);
+                self.line_number(source, end_line).to_string().len()
+            })
+            .max()
+            .unwrap_or_default();
+        self.display_gutter(None, gutter)?;
+        writeln!(&mut self.writer)?;
+        self.display_label(source, &diagnostic.primary, label_color, gutter)?;
+        for label in &diagnostic.secondary {
+            self.display_label(source, label, Cyan, gutter)?;
+        }
+        if !diagnostic.notes.is_empty() || diagnostic.help.is_some() {
+            self.display_gutter(None, gutter)?;
+            writeln!(&mut self.writer)?;
+        }
+        for note in &diagnostic.notes {
+            let label = locale::message("label.note", &[]);
+            writeln!(
+                &mut self.writer,
+                "{: >1$} = {2}: {3}",
+                "", gutter, label, note
+            )?;
+        }
+        if let Some(help) = &diagnostic.help {
+            let label = locale::message("label.help", &[]);
+            writeln!(
+                &mut self.writer,
+                "{: >1$} = {2}: {3}",
+                "", gutter, label, help
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Tab stops are this many columns apart.
+const TAB_WIDTH: usize = 8;
+
+/// Lines wider than this are cut around the labelled text, with `...` in
+/// place of the rest.
+const MAX_LINE_WIDTH: usize = 100;
+
+/// How many columns before the labelled text a cut line keeps.
+const LINE_MARGIN: usize = 20;
+
+/// How many lines between its first and last a label shows at most.
+const MAX_INNER_LINES: usize = 2;
+
+/// Returns the line and char index within it of the start of `label`, and
+/// those just past its last char, not counting trailing line breaks.
+fn label_lines(source: &SourceFile, label: &Label) -> ((usize, usize), (usize, usize)) {
+    let text = &source.src.as_str()[label.byte_range.clone()];
+    let breaks = text.len() - text.trim_end_matches(['\r', '\n']).len();
+    let end = label.char_range.end - breaks;
+    let start = lookup(source, label.char_range.start);
+    if end <= label.char_range.start {
+        return (start, start);
+    }
+    let (line, index) = lookup(source, end - 1);
+    (start, (line, index + 1))
+}
+
+/// Returns the line and column of `char_pos`, counting the end of the
+/// source as the end of its last line.
+fn lookup(source: &SourceFile, char_pos: usize) -> (usize, usize) {
+    let last = source.lines.len() - 2;
+    match source.lookup_line_column(char_pos) {
+        (line, _) if line > last => (last, source.lines[last + 1] - source.lines[last] - 1),
+        position => position,
+    }
+}
+
+/// A source line as it appears in a terminal, with tabs expanded to the
+/// next tab stop and wide chars taking two columns.
+struct DisplayLine {
+    /// The columns of each char and what it is shown as
+    cells: Vec<(Range<usize>, String)>,
+    width: usize,
+    /// The columns shown, which are all of them unless the line is cut
+    window: Range<usize>,
+}
+
+impl DisplayLine {
+    fn new(line: &str) -> Self {
+        let mut cells = Vec::new();
+        let mut width = 0;
+        for c in line.chars() {
+            let (text, w) = match c {
+                '\t' => {
+                    let w = TAB_WIDTH - width % TAB_WIDTH;
+                    (" ".repeat(w), w)
+                }
+                c => (
+                    c.to_string(),
+                    UnicodeWidthChar::width(c).unwrap_or_default(),
+                ),
+            };
+            cells.push((width..width + w, text));
+            width += w;
+        }
+        DisplayLine {
+            cells,
+            width,
+            window: 0..width,
+        }
+    }
+
+    /// Returns the column the `index`-th char starts at, or the width of
+    /// the line for the index past its end.
+    fn column(&self, index: usize) -> usize {
+        self.cells
+            .get(index)
+            .map_or(self.width, |(columns, _)| columns.start)
+    }
+
+    /// Cuts the line if it is too wide, keeping `columns` in sight as far
+    /// as they fit.
+    fn focus(&mut self, columns: Range<usize>) {
+        if self.width > MAX_LINE_WIDTH {
+            let start = columns.start.saturating_sub(LINE_MARGIN);
+            let start = start.min(self.width - MAX_LINE_WIDTH);
+            self.window = start..start + MAX_LINE_WIDTH;
+        }
+    }
+
+    /// Returns where `column` is printed, after the `...` of a line cut at
+    /// the start.
+    fn offset(&self, column: usize) -> usize {
+        let ellipsis = if self.window.start > 0 { 3 } else { 0 };
+        column.clamp(self.window.start, self.window.end) - self.window.start + ellipsis
+    }
+}
+
+impl fmt::Display for DisplayLine {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.window.start > 0 {
+            f.write_str("...")?;
+        }
+        let mut column = self.window.start;
+        for (columns, text) in &self.cells {
+            if columns.start < self.window.start || columns.end > self.window.end {
+                continue;
+            }
+            write!(f, "{: <1$}{2}", "", columns.start - column, text)?;
+            column = columns.end;
+        }
+        if self.window.end < self.width {
+            write!(f, "{: <1$}...", "", self.window.end - column)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dialect::Dialect;
+    use crate::lexer::Lexer;
+    use crate::preprocess::Preprocessor;
+    use crate::source::LineMarker;
+    use std::path::PathBuf;
+    use termcolor::NoColor;
+
+    #[test]
+    fn test_human_emitter() {
+        let src = "#line 7 \"gen.c\"\nint 字 = 08 + \"abc;\n";
+        let source = SourceFile::new(PathBuf::from("main.c"), src.to_string());
+        source.add_line_marker(LineMarker {
+            line: 1,
+            presumed_line: 7,
+            path: Some("gen.c".to_string()),
+        });
+        let mut lexer = Lexer::new(src);
+        while lexer.advance_token().is_some() {}
+        let mut diagnostics = lexer.take_diagnostics();
+        let byte_start = src.find('字').unwrap();
+        let char_start = src[..byte_start].chars().count();
+        let ident = Label::new(
+            byte_start..byte_start + 3,
+            char_start..char_start + 1,
+            "in this declaration".into(),
+        );
+        diagnostics[0] = diagnostics[0]
+            .clone()
+            .with_secondary(ident)
+            .with_help("remove the leading 0".into());
+
+        let mut emitter = HumanEmitter::new(NoColor::new(Vec::new()));
+        for diagnostic in &diagnostics {
+            emitter.emit(&source, diagnostic).unwrap();
+        }
+        let mut physical = HumanEmitter::new(NoColor::new(Vec::new())).with_physical(true);
+        physical.emit(&source, &diagnostics[1]).unwrap();
+
+        let output = String::from_utf8(emitter.into_inner().into_inner()).unwrap();
+        assert_eq!(
+            output,
+            r#"gen.c:7:10: error[E0018]: invalid digit in octal constant
+  |
+7 | int 字 = 08 + "abc;
+  |           ^
+7 | int 字 = 08 + "abc;
+  |     ^^ in this declaration
+  |
+  = note: a constant starting with `0` is octal, so `8` and `9` are invalid
+  = help: remove the leading 0
+gen.c:7:14: error[E0002]: missing terminating '"' character
+  |
+7 | int 字 = 08 + "abc;
+  |               ^^^^^
+  |
+  = note: a string literal ends on the line it starts on, unless the new-line is escaped with a backslash
+"#
+        );
+        let output = String::from_utf8(physical.into_inner().into_inner()).unwrap();
+        assert!(output.starts_with("main.c:2:14: error[E0002]: missing terminating"));
+        assert!(output.contains("\n2 | int 字"));
+    }
+
+    #[test]
+    fn test_multiline_labels() {
+        let long = "x".repeat(120);
+        let src = format!(
+            "char *s = \"ab\\\ncd;\n\tint\t字 = 08;\nint {} = 09;\n/* a\n b\n c\n d\n e",
+            long
+        );
+        let source = SourceFile::new(PathBuf::from("main.c"), src.clone());
+        let mut lexer = Lexer::new(&src);
+        while lexer.advance_token().is_some() {}
+        let mut emitter = HumanEmitter::new(NoColor::new(Vec::new()));
+        for mut diagnostic in lexer.take_diagnostics() {
+            diagnostic.notes.clear();
+            emitter.emit(&source, &diagnostic).unwrap();
+        }
+
+        let output = String::from_utf8(emitter.into_inner().into_inner()).unwrap();
+        let cut = format!("...{} = 09;", "x".repeat(94));
+        assert_eq!(
+            output,
+            format!(
+                r#"main.c:1:11: error[E0002]: missing terminating '"' character
+  |
+1 |   char *s = "ab\
+  |  ___________^
+2 | | cd;
+  | |___^
+main.c:3:11: error[E0018]: invalid digit in octal constant
+  |
+3 |         int     字 = 08;
+  |                       ^
+main.c:4:129: error[E0018]: invalid digit in octal constant
+  |
+4 | {}
+  | {: >101}^
+main.c:5:1: error[E0001]: unterminated comment
+  |
+5 |   /* a
+  |  _^
+6 | |  b
+...
+9 | |  e
+  | |__^
+"#,
+                cut, ""
+            )
+        );
+    }
+
+    #[test]
+    fn test_explanations() {
+        for code in (1..=20).map(|i| format!("E{:04}", i)) {
+            let explanation = explain(&code).unwrap();
+            let examples: Vec<_> = explanation
+                .split("```c\n")
+                .skip(1)
+                .map(|example| &example[..example.find("```").unwrap()])
+                .collect();
+            if examples.is_empty() {
+                continue;
+            }
+            // The erroneous example reports the error it explains, and the
+            // fixed one none.
+            let codes = |example: &str| -> Vec<&'static str> {
+                let source = SourceFile::new(PathBuf::from("example.c"), example.to_string());
+                Preprocessor::new(source)
+                    .with_dialect(Dialect::C23)
+                    .filter_map(|token| match token.kind {
+                        Error(kind) => Some(kind.code()),
+                        _ => None,
+                    })
+                    .collect()
+            };
+            let found = codes(examples[0]);
+            assert!(found.contains(&code.as_str()), "{}: {:?}", code, found);
+            assert_eq!(codes(examples[1]), [] as [&str; 0], "{}", code);
+        }
+        assert_eq!(explain("E0999"), None);
+    }
+
+    #[test]
+    fn test_collect_diagnostics() {
+        let src = "'\\q' 1e999 @\n";
+        let source = SourceFile::new(PathBuf::from("main.c"), src.to_string());
+        let mut lexer = Lexer::new(src);
+        let mut collected: Vec<Diagnostic> = Vec::new();
+        while lexer.advance_token().is_some() {
+            for diagnostic in lexer.take_diagnostics() {
+                collected.emit(&source, &diagnostic).unwrap();
+            }
+        }
+        let summary: Vec<_> = collected
+            .iter()
+            .map(|d| (d.severity, d.code.unwrap(), d.primary.byte_range.clone()))
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                (Severity::Warning, "W0005", 1..3),
+                (Severity::Warning, "W0003", 5..10),
+                (Severity::Error, "E0004", 11..12),
+            ]
+        );
+    }
+}
@@ -0,0 +1,121 @@
+//! Revisions of the C standard.
+
+use std::fmt;
+use std::str::FromStr;
+
+/// A revision of the C standard, deciding what the lexer accepts.
+///
+/// Dialects are ordered chronologically, so `dialect >= Dialect::C11`
+/// reads as "C11 or later".
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Dialect {
+    /// ANSI X3.159-1989, also known as C90
+    C89,
+    /// ISO/IEC 9899:1999
+    #[default]
+    C99,
+    /// ISO/IEC 9899:2011
+    C11,
+    /// ISO/IEC 9899:2018
+    C17,
+    /// ISO/IEC 9899:2024
+    C23,
+}
+
+impl Dialect {
+    /// Returns `true` if universal character names (`\uXXXX` and
+    /// `\UXXXXXXXX`) are part of the dialect.
+    pub fn has_ucn(self) -> bool {
+        self >= Dialect::C99
+    }
+
+    /// Returns `true` if `//` starts a comment.
+    pub fn has_line_comments(self) -> bool {
+        self >= Dialect::C99
+    }
+
+    /// Returns `true` if `<:`, `:>`, `<%`, `%>`, `%:` and `%:%:` are
+    /// punctuators. They came with Amendment 1 to C90, which we treat as
+    /// part of C99.
+    pub fn has_digraphs(self) -> bool {
+        self >= Dialect::C99
+    }
+
+    /// Returns `true` if hexadecimal floating constants such as `0x1.8p-3`
+    /// are supported.
+    pub fn has_hexadecimal_floats(self) -> bool {
+        self >= Dialect::C99
+    }
+
+    /// Returns `true` if the `ll` and `LL` integer suffixes are supported.
+    pub fn has_long_long(self) -> bool {
+        self >= Dialect::C99
+    }
+
+    /// Returns `true` if the `u8`, `u` and `U` encoding prefixes of string
+    /// literals, and the `u` and `U` ones of character constants, are supported.
+    pub fn has_unicode_literals(self) -> bool {
+        self >= Dialect::C11
+    }
+
+    /// Returns `true` if `u8` character constants such as `u8'a'` are supported.
+    pub fn has_utf8_char_consts(self) -> bool {
+        self >= Dialect::C23
+    }
+
+    /// Returns `true` if binary integer constants such as `0b1010` are supported.
+    pub fn has_binary_constants(self) -> bool {
+        self >= Dialect::C23
+    }
+
+    /// Returns `true` if `'` may separate the digits of a constant, as in `1'000'000`.
+    pub fn has_digit_separators(self) -> bool {
+        self >= Dialect::C23
+    }
+
+    /// Returns `true` if `#elifdef`, `#elifndef` and `#warning` are directives.
+    pub fn has_c23_directives(self) -> bool {
+        self >= Dialect::C23
+    }
+
+    /// Returns the value of the predefined macro `__STDC_VERSION__`, which
+    /// C89 does not define.
+    pub fn stdc_version(self) -> Option<&'static str> {
+        match self {
+            Dialect::C89 => None,
+            Dialect::C99 => Some("199901L"),
+            Dialect::C11 => Some("201112L"),
+            Dialect::C17 => Some("201710L"),
+            Dialect::C23 => Some("202311L"),
+        }
+    }
+}
+
+impl FromStr for Dialect {
+    type Err = String;
+
+    /// Parses the names accepted by GCC's `-std=` option.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "c89" | "c90" => Ok(Dialect::C89),
+            "c99" => Ok(Dialect::C99),
+            "c11" => Ok(Dialect::C11),
+            "c17" | "c18" => Ok(Dialect::C17),
+            "c23" | "c2x" => Ok(Dialect::C23),
+            _ => Err(format!("unknown C standard `{}`", s)),
+        }
+    }
+}
+
+impl fmt::Display for Dialect {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Dialect::C89 => "c89",
+            Dialect::C99 => "c99",
+            Dialect::C11 => "c11",
+            Dialect::C17 => "c17",
+            Dialect::C23 => "c23",
+        };
+        f.write_str(name)
+    }
+}
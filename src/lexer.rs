@@ -1,60 +1,470 @@
+//! The lexer itself.
+
+use crate::diagnostics::Diagnostic;
+use crate::dialect::Dialect;
+use crate::literal::{DataModel, FloatStatus, LiteralError};
+use crate::preprocess::line_marker;
+use crate::source::LineMarker;
 use crate::token::*;
+use crate::unicode;
+use std::borrow::Cow;
+use std::ops::Range;
 
+/// The character returned by [`Lexer::peek_char`] past the end of input.
 pub const EOF: char = '\0';
 
+/// What the lexer produces.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum LexMode {
+    /// Tokens, as seen after translation phase 7 (Section 6.4)
+    #[default]
+    Normal,
+    /// Preprocessing tokens, as seen after translation phase 3: keywords
+    /// are identifiers, numbers are [`PpNumber`]s, the file names of
+    /// `#include` directives are [`HeaderName`]s, and stray characters
+    /// are [`Other`] rather than errors.
+    ///
+    /// [`Lexer::convert_token`] turns them into tokens.
+    Preprocessing,
+}
+
+/// How far the current line has got into an `#include` directive, so
+/// that the file name is lexed as a header name.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum IncludeState {
+    Hash,
+    Include,
+    None,
+}
+
+/// A C99 lexer over a borrowed source string.
+///
+/// Call [`advance_token`](Lexer::advance_token) repeatedly to get every
+/// token including whitespace and comments, or use [`iter`](Lexer::iter)
+/// to get only the meaningful ones.
 pub struct Lexer<'a> {
     src: &'a str,
     byte_cursor: usize,
     char_cursor: usize,
+    mode: LexMode,
+    dialect: Dialect,
+    data_model: DataModel,
+    trigraphs: bool,
+    include_state: IncludeState,
+    /// Whether no token but trivia has been found on the current line
+    at_line_start: bool,
+    /// Whether whitespace or a comment has been found since the last token
+    has_leading_space: bool,
+    /// Whether the head of a directive has been found on the current line
+    in_directive: bool,
+    /// Whether a line splice has been skipped within the current token
+    spliced: bool,
+    /// Whether a trigraph has been replaced within the current token
+    has_trigraphs: bool,
+    /// The last char consumed, or [`EOF`] at the beginning of input
+    last_char: char,
+    /// Whether a digit separator not between two digits has been found
+    /// within the current constant
+    invalid_separator: bool,
+    diagnostics: Vec<Diagnostic>,
+    /// The head and the tokens so far of the `#line` directive on the
+    /// current line, if any
+    line_directive: Option<Vec<Token<'a>>>,
+    line_markers: Vec<LineMarker>,
 }
 
 impl<'a> Lexer<'a> {
+    /// Creates a lexer positioned at the beginning of `src`.
     pub fn new(src: &'a str) -> Self {
         Self {
             src,
             byte_cursor: 0,
             char_cursor: 0,
+            mode: LexMode::default(),
+            dialect: Dialect::default(),
+            data_model: DataModel::default(),
+            trigraphs: false,
+            include_state: IncludeState::None,
+            at_line_start: true,
+            has_leading_space: false,
+            in_directive: false,
+            spliced: false,
+            has_trigraphs: false,
+            last_char: EOF,
+            invalid_separator: false,
+            diagnostics: Vec::new(),
+            line_directive: None,
+            line_markers: Vec::new(),
         }
     }
 
+    /// Enables trigraph replacement (translation phase 1), which is off by default.
+    pub fn with_trigraphs(mut self, trigraphs: bool) -> Self {
+        self.trigraphs = trigraphs;
+        self
+    }
+
+    /// Sets what the lexer produces; the default is [`LexMode::Normal`].
+    pub fn with_mode(mut self, mode: LexMode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    /// Sets the revision of the C standard to lex; the default is C99.
+    pub fn with_dialect(mut self, dialect: Dialect) -> Self {
+        self.dialect = dialect;
+        self
+    }
+
+    /// Sets the data model of the target, which decides the format of
+    /// `long double` constants; the default is LP64.
+    pub fn with_data_model(mut self, data_model: DataModel) -> Self {
+        self.data_model = data_model;
+        self
+    }
+
+    /// Consumes the lexer, returning an iterator over all tokens
+    /// except whitespace and comments.
     pub fn iter(mut self) -> impl Iterator<Item = Token<'a>> {
-        std::iter::from_fn(move || self.advance_token())
-            .filter(|token| token.kind != Whitespace && token.kind != Comment)
+        std::iter::from_fn(move || self.advance_token()).filter(|token| !token.kind.is_trivia())
+    }
+
+    /// Consumes the lexer, returning an iterator over all tokens except
+    /// trivia, each carrying the trivia around it, and a last item for
+    /// the end of input. Nothing of the source text is lost.
+    pub fn lossless(mut self) -> impl Iterator<Item = LosslessToken<'a>> {
+        let mut carried = Vec::new();
+        let mut done = false;
+        let mut tokens = std::iter::from_fn(move || self.advance_token()).peekable();
+        std::iter::from_fn(move || {
+            if done {
+                return None;
+            }
+            let mut leading = std::mem::take(&mut carried);
+            leading.extend(std::iter::from_fn(|| {
+                tokens.next_if(|token| token.kind.is_trivia())
+            }));
+            let token = tokens.next();
+            let mut trailing = Vec::new();
+            match &token {
+                // An unterminated literal already ends its line.
+                Some(token) if token.as_str().ends_with('\n') => (),
+                Some(_) => {
+                    while let Some(trivia) = tokens.next_if(|token| token.kind.is_trivia()) {
+                        match split_after_newline(&trivia) {
+                            Some((head, tail)) => {
+                                trailing.push(head);
+                                carried.extend(tail);
+                                break;
+                            }
+                            None => trailing.push(trivia),
+                        }
+                    }
+                }
+                None => done = true,
+            }
+            Some(LosslessToken {
+                leading,
+                token,
+                trailing,
+            })
+        })
+    }
+
+    /// Returns the diagnostics of the errors and warnings found since the
+    /// last call, leaving none behind. Every error token comes with one.
+    pub fn take_diagnostics(&mut self) -> Vec<Diagnostic> {
+        std::mem::take(&mut self.diagnostics)
+    }
+
+    /// Returns the effects of the valid `#line` directives whose lines have
+    /// ended since the last call, leaving none behind. Record them with
+    /// [`SourceFile::add_line_marker`](crate::SourceFile::add_line_marker)
+    /// to follow the directives without preprocessing.
+    pub fn take_line_markers(&mut self) -> Vec<LineMarker> {
+        std::mem::take(&mut self.line_markers)
     }
 
+    /// Converts a preprocessing token to a token (translation phase 7),
+    /// lexing it again in [`LexMode::Normal`] with the options of this lexer.
+    ///
+    /// Errors and warnings are those of the normal mode, e.g. for invalid
+    /// escape sequences, and a [`PpNumber`] that is not a single constant,
+    /// such as `1.2.3`, becomes an [`InvalidNumber`] error. Header names
+    /// are left alone, since only `#include` directives make sense of them.
+    pub fn convert_token(&mut self, token: &Token<'a>) -> Token<'a> {
+        if let HeaderName | Whitespace | Comment | LineSplice | Error(_) = token.kind {
+            return token.clone();
+        }
+        let mut lexer = Lexer {
+            byte_cursor: token.byte_range.start,
+            char_cursor: token.char_range.start,
+            at_line_start: false,
+            ..Lexer::new(token.src)
+        }
+        .with_dialect(self.dialect)
+        .with_data_model(self.data_model)
+        .with_trigraphs(self.trigraphs);
+        let mut converted = lexer.advance_token().unwrap();
+        if converted.byte_range != token.byte_range {
+            converted = token.clone();
+            converted.kind = Error(InvalidNumber);
+            lexer.diagnostics = Diagnostic::from_token(&converted).into_iter().collect();
+        }
+        converted.at_line_start = token.at_line_start;
+        converted.has_leading_space = token.has_leading_space;
+        self.diagnostics.append(&mut lexer.diagnostics);
+        converted
+    }
+
+    /// Advances the lexer by one token and returns it, or `None` at the end of input.
     pub fn advance_token(&mut self) -> Option<Token<'a>> {
         let initial_byte_cursor = self.byte_cursor;
         let initial_char_cursor = self.char_cursor;
+        self.spliced = false;
+        self.has_trigraphs = false;
+
+        // Splices between tokens are tokens on their own, so that no
+        // token but a spliced one begins with a backslash.
+        let splice_len = splice_len(self.remaining(), self.trigraphs);
+        if splice_len > 0 {
+            self.byte_cursor += splice_len;
+            self.char_cursor += splice_len;
+            let mut token = Token::new(
+                LineSplice,
+                initial_byte_cursor..self.byte_cursor,
+                initial_char_cursor..self.char_cursor,
+                self.src,
+            );
+            token.at_line_start = self.at_line_start;
+            token.has_leading_space = self.has_leading_space;
+            token.in_directive = self.in_directive;
+            return Some(token);
+        }
 
         let first_char = self.bump_char()?;
 
         let token_kind = match first_char {
             c if c.is_whitespace() => self.whitespace(),
-            '/' if self.peek_char(0) == '/' => self.line_comment(),
+            '/' if self.peek_char(0) == '/' && self.dialect.has_line_comments() => {
+                self.line_comment()
+            }
             '/' if self.peek_char(0) == '*' => self.block_comment(),
-            'L' if self.peek_char(0) == '"' => {
-                self.bump_char();
-                self.string_literal()
+            'L' | 'u' | 'U' => match self.encoding_prefix(first_char) {
+                Some(encoding) => self.prefixed_literal(encoding),
+                None => self.ident_or_keyword(initial_byte_cursor, None),
+            },
+            '#' | '%' if self.is_directive_start(first_char) => self.directive(),
+            '<' | '"' if self.is_header_name_start() => self.header_name(first_char),
+            '"' => self.string_literal(Encoding::Plain),
+            '\'' => self.char_const(Encoding::Plain),
+            '_' | 'a'..='z' | 'A'..='Z' => self.ident_or_keyword(initial_byte_cursor, None),
+            '\\' if self.is_ucn_start(0) => {
+                let error = self.eat_ucn(true);
+                self.ident_or_keyword(initial_byte_cursor, error)
             }
-            'L' if self.peek_char(0) == '\'' => {
-                self.bump_char();
-                self.char_const()
+            c if !c.is_ascii() && unicode::is_ident_start(self.dialect, c) => {
+                self.ident_or_keyword(initial_byte_cursor, None)
             }
-            '"' => self.string_literal(),
-            '\'' => self.char_const(),
-            '_' | 'a'..='z' | 'A'..='Z' => self.ident_or_keyword(initial_byte_cursor),
             digit if digit.is_ascii_digit() => self.number(digit),
             '.' if self.peek_char(0).is_ascii_digit() => self.number('.'),
             symbol if symbol.is_ascii_punctuation() => self.punct(symbol),
             _ => Error(UnexpectedCharacter),
         };
 
-        Some(Token::new(
+        let mut token = Token::new(
             token_kind,
             initial_byte_cursor..self.byte_cursor,
             initial_char_cursor..self.char_cursor,
             self.src,
-        ))
+        );
+        token.spliced = self.spliced;
+        token.has_trigraphs = self.has_trigraphs;
+        token.at_line_start = self.at_line_start;
+        token.has_leading_space = self.has_leading_space;
+        token.in_directive = self.in_directive;
+        match (self.mode, token.kind) {
+            (LexMode::Normal, Const(Float)) => self.check_float(&token),
+            (LexMode::Normal, Const(Char(_))) | (LexMode::Normal, StrLit(_)) => {
+                self.check_escapes(&mut token)
+            }
+            (LexMode::Preprocessing, Error(UnknownPunctuator))
+            | (LexMode::Preprocessing, Error(UnexpectedCharacter)) => token.kind = Other,
+            _ => (),
+        }
+        self.diagnostics.extend(Diagnostic::from_token(&token));
+        self.track_layout(&token);
+        self.track_include(&token);
+        self.track_line_directive(&token);
+        Some(token)
+    }
+
+    /// Records whether the token after `token` starts a line, follows
+    /// whitespace or belongs to a directive. A comment counts as whitespace,
+    /// but a line splice does not.
+    fn track_layout(&mut self, token: &Token) {
+        match token.kind {
+            Whitespace => {
+                if token.spelling().contains('\n') {
+                    self.at_line_start = true;
+                    self.in_directive = false;
+                }
+                self.has_leading_space = true;
+            }
+            Directive(_) => {
+                self.at_line_start = false;
+                self.has_leading_space = false;
+                self.in_directive = true;
+            }
+            Comment => self.has_leading_space = true,
+            LineSplice => (),
+            // An unterminated literal takes the new-line that ends it.
+            Error(_) if token.spelling().ends_with('\n') => {
+                self.at_line_start = true;
+                self.has_leading_space = false;
+                self.in_directive = false;
+            }
+            _ => {
+                self.at_line_start = false;
+                self.has_leading_space = false;
+            }
+        }
+    }
+
+    /// Collects the tokens of a `#line` directive, and records its effect
+    /// once its line ends. Macros are not expanded.
+    fn track_line_directive(&mut self, token: &Token<'a>) {
+        match token.kind {
+            Directive(DirectiveKind::Line) => self.line_directive = Some(vec![token.clone()]),
+            kind if token.in_directive && !kind.is_trivia() => {
+                if let Some(tokens) = &mut self.line_directive {
+                    tokens.push(token.clone());
+                }
+            }
+            _ => (),
+        }
+        if !self.in_directive {
+            if let Some(tokens) = self.line_directive.take() {
+                self.line_markers.extend(line_marker(self.src, &tokens));
+            }
+        }
+    }
+
+    /// Follows the tokens of an `#include` directive up to the file name.
+    fn track_include(&mut self, token: &Token) {
+        self.include_state = match (self.include_state, token.kind) {
+            (_, Punct(PunctKind::Hash)) if token.at_line_start => IncludeState::Hash,
+            _ if self.at_line_start => IncludeState::None,
+            (state, Whitespace) | (state, Comment) | (state, LineSplice) => state,
+            (IncludeState::Hash, Ident) if token.spelling() == "include" => IncludeState::Include,
+            _ => IncludeState::None,
+        }
+    }
+
+    /// Returns `true` if the `#` or `%:` whose first char has just been
+    /// consumed is the first token of a line in normal mode, and not `##`.
+    fn is_directive_start(&self, first_char: char) -> bool {
+        if self.mode != LexMode::Normal || !self.at_line_start {
+            return false;
+        }
+        match first_char {
+            '#' => self.peek_char(0) != '#',
+            _ => {
+                self.dialect.has_digraphs()
+                    && self.peek_char(0) == ':'
+                    && !(self.peek_char(1) == '%' && self.peek_char(2) == ':')
+            }
+        }
+    }
+
+    /// Section 6.10: lexes the head of a directive whose `#` has just been
+    /// consumed, up to the end of the directive name if any.
+    ///
+    /// The rest of the line is lexed as usual, with tokens marked as
+    /// [`in_directive`](Token::in_directive), so that problems there are
+    /// still reported.
+    fn directive(&mut self) -> TokenKind {
+        if self.last_char == '%' {
+            self.bump_char();
+        }
+        let mut end = (self.byte_cursor, self.char_cursor);
+        self.eat_directive_space();
+        let name_start = self.byte_cursor;
+        while self.peek_char(0).is_ascii_alphanumeric() || self.peek_char(0) == '_' {
+            self.bump_char();
+        }
+        let kind = match (self.byte_cursor == name_start, self.peek_char(0)) {
+            // Only a comment or the end of the line may follow a null directive.
+            (true, '\n') | (true, EOF) => DirectiveKind::Null,
+            (true, '/') if matches!(self.peek_char(1), '/' | '*') => DirectiveKind::Null,
+            _ => DirectiveKind::from_name(&self.logical_str(name_start), self.dialect),
+        };
+        if kind != DirectiveKind::Null {
+            end = (self.byte_cursor, self.char_cursor);
+        }
+        (self.byte_cursor, self.char_cursor) = end;
+        Directive(kind)
+    }
+
+    /// Consumes the whitespace other than new-lines and the closed block
+    /// comments after the `#` of a directive.
+    fn eat_directive_space(&mut self) {
+        loop {
+            match self.peek_char(0) {
+                '\n' => return,
+                c if c.is_whitespace() => {
+                    self.bump_char();
+                }
+                '/' if self.peek_char(1) == '*' && self.is_closed_comment() => {
+                    self.bump_char();
+                    self.block_comment();
+                }
+                _ => return,
+            }
+        }
+    }
+
+    /// Returns `true` if the block comment under the cursor is closed.
+    fn is_closed_comment(&self) -> bool {
+        let mut last = EOF;
+        for c in logical_chars(self.remaining(), self.trigraphs).skip(2) {
+            if last == '*' && c == '/' {
+                return true;
+            }
+            last = c;
+        }
+        false
+    }
+
+    /// Returns `true` if the `<` or `"` just consumed starts the file name
+    /// of an `#include` directive in preprocessing mode, which is only a
+    /// header name if it is closed on the same line.
+    fn is_header_name_start(&self) -> bool {
+        if self.mode != LexMode::Preprocessing || self.include_state != IncludeState::Include {
+            return false;
+        }
+        let close = match self.last_char {
+            '<' => '>',
+            _ => '"',
+        };
+        logical_chars(self.remaining(), self.trigraphs)
+            .take_while(|&c| c != '\n')
+            .any(|c| c == close)
+    }
+
+    /// Section 6.4.7: unlike string literals, header names have no escape
+    /// sequences.
+    fn header_name(&mut self, open: char) -> TokenKind {
+        let close = match open {
+            '<' => '>',
+            _ => '"',
+        };
+        while let Some(c) = self.bump_char() {
+            if c == close {
+                break;
+            }
+        }
+        HeaderName
     }
 
     fn whitespace(&mut self) -> TokenKind {
@@ -87,29 +497,71 @@ impl<'a> Lexer<'a> {
         }
     }
 
-    fn ident_or_keyword(&mut self, initial_byte_cursor: usize) -> TokenKind {
-        self.eat_ident_or_keyword();
-        match &self.src[initial_byte_cursor..self.byte_cursor] {
-            // Section 6.4.1 Keywords
-            "auto" | "break" | "case" | "char" | "const" | "continue" | "default" | "do"
-            | "double" | "else" | "enum" | "extern" | "float" | "for" | "goto" | "if"
-            | "inline" | "int" | "long" | "register" | "restrict" | "return" | "short"
-            | "signed" | "sizeof" | "static" | "struct" | "switch" | "typedef" | "union"
-            | "unsigned" | "void" | "volatile" | "while" | "_Bool" | "_Complex" | "_Imaginary" => {
-                Keyword
-            }
+    /// `error` is the error found in the first character of the identifier, if any.
+    fn ident_or_keyword(
+        &mut self,
+        initial_byte_cursor: usize,
+        error: Option<ErrorKind>,
+    ) -> TokenKind {
+        if let Some(error) = error.or(self.eat_ident_or_keyword()) {
+            return Error(error);
+        }
+        if self.mode == LexMode::Preprocessing {
+            return Ident;
+        }
+        match self.logical_str(initial_byte_cursor).parse::<KeywordKind>() {
+            Ok(keyword) if self.dialect >= keyword.since() => Keyword(keyword),
             _ => Ident,
         }
     }
 
-    fn string_literal(&mut self) -> TokenKind {
+    /// Returns the encoding if `first_char` and the chars after the cursor
+    /// are the prefix of a character constant or string literal.
+    fn encoding_prefix(&self, first_char: char) -> Option<Encoding> {
+        let (encoding, quote) = match (first_char, self.peek_char(0)) {
+            ('u', '8') => (Encoding::Utf8, self.peek_char(1)),
+            ('u', quote) => (Encoding::Utf16, quote),
+            ('U', quote) => (Encoding::Utf32, quote),
+            ('L', quote) => (Encoding::Wide, quote),
+            _ => return None,
+        };
+        let is_supported = match (encoding, quote) {
+            (Encoding::Wide, '"') | (Encoding::Wide, '\'') => true,
+            (Encoding::Utf8, '\'') => self.dialect.has_utf8_char_consts(),
+            (_, '"') | (_, '\'') => self.dialect.has_unicode_literals(),
+            _ => false,
+        };
+        match is_supported {
+            true => Some(encoding),
+            false => None,
+        }
+    }
+
+    /// Lexes a character constant or string literal after the first char of
+    /// its encoding prefix.
+    fn prefixed_literal(&mut self, encoding: Encoding) -> TokenKind {
+        if let Encoding::Utf8 = encoding {
+            self.bump_char();
+        }
+        match self.bump_char() {
+            Some('"') => self.string_literal(encoding),
+            _ => self.char_const(encoding),
+        }
+    }
+
+    fn string_literal(&mut self, encoding: Encoding) -> TokenKind {
+        let initial_byte_cursor = self.byte_cursor;
+        let initial_char_cursor = self.char_cursor;
+        let kind = self.eat_string_literal(encoding);
+        self.check_trigraphs(initial_byte_cursor, initial_char_cursor);
+        kind
+    }
+
+    fn eat_string_literal(&mut self, encoding: Encoding) -> TokenKind {
         while let Some(c) = self.bump_char() {
             match c {
-                '"' => return StrLit,
-                '\\' if self.peek_char(0) == '"'
-                    || self.peek_char(0) == '\\'
-                    || self.peek_char(0) == '\n' =>
-                {
+                '"' => return StrLit(encoding),
+                '\\' if self.peek_char(0) == '"' || self.peek_char(0) == '\\' => {
                     self.bump_char();
                 }
                 '\n' => return Error(UnterminatedString),
@@ -119,14 +571,84 @@ impl<'a> Lexer<'a> {
         Error(UnterminatedString)
     }
 
-    fn char_const(&mut self) -> TokenKind {
+    /// Warns about every trigraph from `byte_begin` to the cursor, whether
+    /// it has been replaced or not, like GCC's `-Wtrigraphs`.
+    fn check_trigraphs(&mut self, byte_begin: usize, char_begin: usize) {
+        let kind = match self.trigraphs {
+            true => TrigraphConverted,
+            false => TrigraphIgnored,
+        };
+        let text = &self.src[byte_begin..self.byte_cursor];
+        let mut byte_offset = 0;
+        let mut char_offset = 0;
+        while let Some(c) = text[byte_offset..].chars().next() {
+            if trigraph(&text[byte_offset..]).is_some() {
+                let byte_cursor = byte_begin + byte_offset;
+                let char_cursor = char_begin + char_offset;
+                self.diagnostics.push(Diagnostic::from_warning(
+                    kind,
+                    byte_cursor..byte_cursor + 3,
+                    char_cursor..char_cursor + 3,
+                ));
+                byte_offset += 3;
+                char_offset += 3;
+            } else {
+                byte_offset += c.len_utf8();
+                char_offset += 1;
+            }
+        }
+    }
+
+    /// Warns if the value of a floating constant overflows to infinity or
+    /// underflows to zero.
+    fn check_float(&mut self, token: &Token) {
+        let kind = match token.float_value(self.data_model).map(|value| value.status) {
+            Ok(FloatStatus::Overflow) => FloatOverflow,
+            Ok(FloatStatus::Underflow) => FloatUnderflow,
+            _ => return,
+        };
+        self.diagnostics.push(Diagnostic::from_warning(
+            kind,
+            token.byte_range.clone(),
+            token.char_range.clone(),
+        ));
+    }
+
+    /// Validates the escape sequences of a character constant or string
+    /// literal, turning it into an error token if one is invalid.
+    fn check_escapes(&mut self, token: &mut Token) {
+        let literal = match token.decode_literal(self.dialect, self.data_model) {
+            Ok(literal) => literal,
+            Err(error) => {
+                token.kind = Error(match error {
+                    LiteralError::EmptyHexEscape => EmptyHexEscape,
+                    LiteralError::HexEscapeOutOfRange => HexEscapeOutOfRange,
+                    LiteralError::OctalEscapeOutOfRange => OctalEscapeOutOfRange,
+                    LiteralError::InvalidUniversalCharacterName => InvalidUniversalCharacterName,
+                    LiteralError::Utf8CharacterTooLarge => Utf8CharacterTooLarge,
+                    // Not expected of a literal the lexer has closed.
+                    LiteralError::InvalidLiteral
+                    | LiteralError::IntegerTooLarge
+                    | LiteralError::IncompatibleEncodings => Unknown,
+                });
+                return;
+            }
+        };
+        for escape in literal.unknown_escapes {
+            let (byte_range, char_range) = token.source_ranges(escape);
+            self.diagnostics.push(Diagnostic::from_warning(
+                UnknownEscapeSequence,
+                byte_range,
+                char_range,
+            ));
+        }
+    }
+
+    fn char_const(&mut self, encoding: Encoding) -> TokenKind {
         while let Some(c) = self.bump_char() {
             match c {
-                '\'' => return Const(Char),
-                '\\' if self.peek_char(0) == '\''
-                    || self.peek_char(0) == '\\'
-                    || self.peek_char(0) == '\n' =>
-                {
+                '\'' => return Const(Char(encoding)),
+                '\\' if self.peek_char(0) == '\'' || self.peek_char(0) == '\\' => {
                     self.bump_char();
                 }
                 '\n' => return Error(UnterminatedChar),
@@ -136,122 +658,216 @@ impl<'a> Lexer<'a> {
         Error(UnterminatedChar)
     }
 
+    /// Digit separators in the wrong place take precedence over any other
+    /// error in the constant.
     fn number(&mut self, first_digit: char) -> TokenKind {
-        match first_digit {
-            '0' => match self.peek_char(0) {
-                'x' | 'X' => {
-                    self.bump_char();
-                    self.eat_hexadecimal_constant()
-                }
-                '0'..='7' => self.eat_octal_constant(),
-                '.' | 'e' | 'E' | 'u' | 'U' | 'l' | 'L' => self.eat_decimal_constant(),
-                _ => Const(Integer),
-            },
-            _ => self.eat_decimal_constant(),
+        if self.mode == LexMode::Preprocessing {
+            return self.pp_number();
+        }
+        self.invalid_separator = false;
+        let kind = self.eat_number(first_digit);
+        match self.invalid_separator {
+            true => Error(InvalidDigitSeparator),
+            false => kind,
         }
     }
 
-    fn punct(&mut self, first_symbol: char) -> TokenKind {
-        match first_symbol {
-            '(' | ')' | ',' | ';' | '?' | '[' | ']' | '{' | '}' | '~' => Punct,
-            '!' | '^' | '/' | '*' => {
-                if self.peek_char(0) == '=' {
+    /// Section 6.4.8: a preprocessing number is a digit, optionally after
+    /// a `.`, followed by letters, digits, `_`, `.`, signed exponents and,
+    /// in C23, digit separators.
+    fn pp_number(&mut self) -> TokenKind {
+        loop {
+            match (self.peek_char(0), self.peek_char(1)) {
+                ('e', '+') | ('e', '-') | ('E', '+') | ('E', '-') => {
                     self.bump_char();
                 }
-                Punct
-            }
-            '#' | '=' => {
-                if self.peek_char(0) == first_symbol {
+                ('p', '+') | ('p', '-') | ('P', '+') | ('P', '-')
+                    if self.dialect.has_hexadecimal_floats() =>
+                {
                     self.bump_char();
                 }
-                Punct
+                ('.', _) | ('_', _) => (),
+                (c, _) if c.is_ascii_alphanumeric() => (),
+                ('\'', _) if self.is_digit_separator() => (),
+                _ => return PpNumber,
             }
-            '&' | '+' | '|' => {
-                if self.peek_char(0) == first_symbol || self.peek_char(0) == '=' {
+            self.bump_char();
+        }
+    }
+
+    fn eat_number(&mut self, first_digit: char) -> TokenKind {
+        match first_digit {
+            '0' => match self.peek_char(0) {
+                'x' | 'X' => {
                     self.bump_char();
+                    self.eat_hexadecimal_constant()
                 }
-                Punct
-            }
-            '.' => {
-                if self.peek_char(0) == '.' && self.peek_char(1) == '.' {
-                    self.bump_char();
+                'b' | 'B' if self.dialect.has_binary_constants() => {
                     self.bump_char();
+                    self.eat_binary_constant()
                 }
-                Punct
+                _ => self.eat_octal_constant(),
+            },
+            '.' => {
+                self.eat_decimal_digits();
+                self.eat_floating_exponent_and_suffix()
             }
-            '-' => {
-                if let '-' | '=' | '>' = self.peek_char(0) {
+            _ => self.eat_decimal_constant(),
+        }
+    }
+
+    fn punct(&mut self, first_symbol: char) -> TokenKind {
+        use PunctKind::*;
+        let kind = match first_symbol {
+            '(' => LParen,
+            ')' => RParen,
+            ',' => Comma,
+            ';' => Semi,
+            '?' => Question,
+            '[' => LBracket,
+            ']' => RBracket,
+            '{' => LBrace,
+            '}' => RBrace,
+            '~' => Tilde,
+            '!' => self.punct_if_next('=', BangEqual, Bang),
+            '^' => self.punct_if_next('=', CaretEqual, Caret),
+            '/' => self.punct_if_next('=', SlashEqual, Slash),
+            '*' => self.punct_if_next('=', StarEqual, Star),
+            '#' => self.punct_if_next('#', HashHash, Hash),
+            '=' => self.punct_if_next('=', EqualEqual, Equal),
+            '&' => match self.peek_char(0) {
+                '&' => self.bump_punct(1, AmpAmp),
+                '=' => self.bump_punct(1, AmpEqual),
+                _ => Amp,
+            },
+            '+' => match self.peek_char(0) {
+                '+' => self.bump_punct(1, PlusPlus),
+                '=' => self.bump_punct(1, PlusEqual),
+                _ => Plus,
+            },
+            '|' => match self.peek_char(0) {
+                '|' => self.bump_punct(1, PipePipe),
+                '=' => self.bump_punct(1, PipeEqual),
+                _ => Pipe,
+            },
+            '.' => match (self.peek_char(0), self.peek_char(1)) {
+                ('.', '.') => self.bump_punct(2, Ellipsis),
+                _ => Dot,
+            },
+            '-' => match self.peek_char(0) {
+                '-' => self.bump_punct(1, MinusMinus),
+                '=' => self.bump_punct(1, MinusEqual),
+                '>' => self.bump_punct(1, Arrow),
+                _ => Minus,
+            },
+            '<' => match (self.peek_char(0), self.peek_char(1)) {
+                ('=', _) => self.bump_punct(1, LessEqual),
+                (':', _) if self.dialect.has_digraphs() => self.bump_punct(1, LBracket),
+                ('%', _) if self.dialect.has_digraphs() => self.bump_punct(1, LBrace),
+                ('<', '=') => self.bump_punct(2, LessLessEqual),
+                ('<', _) => self.bump_punct(1, LessLess),
+                _ => Less,
+            },
+            '>' => match (self.peek_char(0), self.peek_char(1)) {
+                ('=', _) => self.bump_punct(1, GreaterEqual),
+                ('>', '=') => self.bump_punct(2, GreaterGreaterEqual),
+                ('>', _) => self.bump_punct(1, GreaterGreater),
+                _ => Greater,
+            },
+            ':' => match self.peek_char(0) {
+                '>' if self.dialect.has_digraphs() => self.bump_punct(1, RBracket),
+                // C23 introduces `::` for attribute prefixes.
+                ':' if self.dialect >= Dialect::C23 => self.bump_punct(1, ColonColon),
+                _ => Colon,
+            },
+            '%' => match (self.peek_char(0), self.peek_char(1), self.peek_char(2)) {
+                ('=', _, _) => self.bump_punct(1, PercentEqual),
+                ('>', _, _) if self.dialect.has_digraphs() => self.bump_punct(1, RBrace),
+                (':', '%', ':') if self.dialect.has_digraphs() => self.bump_punct(3, HashHash),
+                (':', _, _) if self.dialect.has_digraphs() => self.bump_punct(1, Hash),
+                _ => Percent,
+            },
+            _ => return Error(UnknownPunctuator),
+        };
+        Punct(kind)
+    }
+
+    /// Consumes `len` more chars of a punctuator of the given `kind`.
+    fn bump_punct(&mut self, len: usize, kind: PunctKind) -> PunctKind {
+        for _ in 0..len {
+            self.bump_char();
+        }
+        kind
+    }
+
+    /// Returns `long` if the next char is `next`, consuming it, or `short` otherwise.
+    fn punct_if_next(&mut self, next: char, long: PunctKind, short: PunctKind) -> PunctKind {
+        match self.peek_char(0) == next {
+            true => self.bump_punct(1, long),
+            false => short,
+        }
+    }
+
+    fn eat_whitespace(&mut self) {
+        while self.peek_char(0).is_whitespace() && !self.is_eof() {
+            self.bump_char();
+        }
+    }
+
+    /// Returns the first error found in the identifier, if any.
+    fn eat_ident_or_keyword(&mut self) -> Option<ErrorKind> {
+        let mut error = None;
+        loop {
+            match self.peek_char(0) {
+                '_' | 'a'..='z' | 'A'..='Z' | '0'..='9' => {
                     self.bump_char();
                 }
-                Punct
-            }
-            '<' => {
-                match self.peek_char(0) {
-                    '=' | ':' | '%' => {
-                        self.bump_char();
-                    }
-                    '<' if self.peek_char(1) == '=' => {
-                        self.bump_char();
-                        self.bump_char();
-                    }
-                    '<' if self.peek_char(1) != '=' => {
-                        self.bump_char();
-                    }
-                    _ => (),
-                }
-                Punct
-            }
-            '>' => {
-                match self.peek_char(0) {
-                    '=' => {
-                        self.bump_char();
-                    }
-                    '>' if self.peek_char(1) == '=' => {
-                        self.bump_char();
-                        self.bump_char();
-                    }
-                    '>' if self.peek_char(1) != '=' => {
-                        self.bump_char();
-                    }
-                    _ => (),
-                }
-                Punct
-            }
-            ':' => {
-                if self.peek_char(0) == '>' {
+                '\\' if self.is_ucn_start(1) => {
                     self.bump_char();
+                    error = error.or(self.eat_ucn(false));
                 }
-                Punct
-            }
-            '%' => {
-                match self.peek_char(0) {
-                    '=' | '>' => {
-                        self.bump_char();
-                    }
-                    ':' => {
-                        self.bump_char();
-                        if self.peek_char(0) == '%' && self.peek_char(1) == ':' {
-                            self.bump_char();
-                            self.bump_char();
-                        }
-                    }
-                    _ => (),
+                c if !c.is_ascii() && unicode::is_ident_continue(self.dialect, c) => {
+                    self.bump_char();
                 }
-                Punct
+                _ => return error,
             }
-            _ => Error(UnknownPunctuator),
         }
     }
 
-    fn eat_whitespace(&mut self) {
-        while self.peek_char(0).is_whitespace() && !self.is_eof() {
-            self.bump_char();
-        }
+    /// Returns `true` if the `nth` char after the cursor is the `u` or `U`
+    /// of a universal character name, given that a `\` precedes it.
+    fn is_ucn_start(&self, nth: usize) -> bool {
+        self.dialect.has_ucn() && matches!(self.peek_char(nth), 'u' | 'U')
     }
 
-    fn eat_ident_or_keyword(&mut self) {
-        while let '_' | 'a'..='z' | 'A'..='Z' | '0'..='9' = self.peek_char(0) {
-            self.bump_char();
+    /// Eats a universal character name (Section 6.4.3) whose `\` has been
+    /// consumed, and checks it may appear in an identifier.
+    fn eat_ucn(&mut self, is_start: bool) -> Option<ErrorKind> {
+        let len = match self.bump_char() {
+            Some('u') => 4,
+            _ => 8,
+        };
+        let mut value = 0;
+        for _ in 0..len {
+            match self.peek_char(0).to_digit(16) {
+                Some(digit) => {
+                    self.bump_char();
+                    value = value * 16 + digit;
+                }
+                None => return Some(InvalidUniversalCharacterName),
+            }
+        }
+        if !unicode::is_valid_ucn(value) {
+            return Some(InvalidUniversalCharacterName);
+        }
+        let c = std::char::from_u32(value).unwrap();
+        let is_allowed = match is_start {
+            true => unicode::is_ident_start(self.dialect, c),
+            false => unicode::is_ident_continue(self.dialect, c),
+        };
+        match is_allowed {
+            true => None,
+            false => Some(InvalidIdentifierCharacter),
         }
     }
 
@@ -271,6 +887,10 @@ impl<'a> Lexer<'a> {
             self.bump_char();
             self.eat_decimal_digits();
         }
+        self.eat_floating_exponent_and_suffix()
+    }
+
+    fn eat_floating_exponent_and_suffix(&mut self) -> TokenKind {
         if let 'e' | 'E' = self.peek_char(0) {
             self.eat_exponent_part();
         }
@@ -292,6 +912,11 @@ impl<'a> Lexer<'a> {
 
     fn eat_hexadecimal_constant(&mut self) -> TokenKind {
         let has_digits = self.eat_hexadecimal_digits();
+        if let '.' | 'p' | 'P' = self.peek_char(0) {
+            if self.dialect.has_hexadecimal_floats() {
+                return self.eat_hexadecimal_floating_constant(has_digits);
+            }
+        }
         if !has_digits {
             return Error(NoHexadecimalDigits);
         }
@@ -301,89 +926,334 @@ impl<'a> Lexer<'a> {
         }
     }
 
+    /// Section 6.4.4.2: unlike decimal ones, hexadecimal floating constants
+    /// always require a binary exponent.
+    fn eat_hexadecimal_floating_constant(&mut self, mut has_digits: bool) -> TokenKind {
+        if let '.' = self.peek_char(0) {
+            self.bump_char();
+            has_digits |= self.eat_hexadecimal_digits();
+        }
+        let has_exponent = self.eat_binary_exponent_part();
+        let has_valid_suffix = self.eat_floating_suffix();
+        if !has_digits {
+            Error(NoHexadecimalDigits)
+        } else if !has_exponent {
+            Error(MissingBinaryExponent)
+        } else if !has_valid_suffix {
+            Error(InvalidFloatingSuffix)
+        } else {
+            Const(Float)
+        }
+    }
+
+    /// Returns `false` if there is no exponent, or it has no digits.
+    fn eat_binary_exponent_part(&mut self) -> bool {
+        if let 'p' | 'P' = self.peek_char(0) {
+            self.bump_char();
+            if let '+' | '-' = self.peek_char(0) {
+                self.bump_char();
+            }
+            self.eat_decimal_digits()
+        } else {
+            false
+        }
+    }
+
+    fn eat_binary_constant(&mut self) -> TokenKind {
+        let has_digits = self.eat_binary_digits();
+        let has_invalid_digits = self.eat_decimal_digits();
+        let has_valid_suffix = self.eat_integer_suffix();
+        if has_invalid_digits {
+            Error(InvalidBinaryDigit)
+        } else if !has_digits {
+            Error(NoBinaryDigits)
+        } else if !has_valid_suffix {
+            Error(InvalidIntegerSuffix)
+        } else {
+            Const(Integer)
+        }
+    }
+
+    /// Eats what follows the leading `0` of an octal constant, which may
+    /// turn out to be a decimal floating constant such as `09.5`
+    /// (Section 6.4.4.2), so `8` and `9` are only rejected at the end.
     fn eat_octal_constant(&mut self) -> TokenKind {
-        self.eat_octal_digits();
-        match self.eat_integer_suffix() {
-            true => Const(Integer),
-            false => Error(InvalidIntegerSuffix),
+        let digits_begin = self.byte_cursor;
+        self.eat_decimal_digits();
+        if let '.' | 'e' | 'E' = self.peek_char(0) {
+            return self.eat_floating_constant();
+        }
+        let has_invalid_digits = self.logical_str(digits_begin).contains(['8', '9']);
+        let has_valid_suffix = self.eat_integer_suffix();
+        if has_invalid_digits {
+            Error(InvalidOctalDigit)
+        } else if !has_valid_suffix {
+            Error(InvalidIntegerSuffix)
+        } else {
+            Const(Integer)
         }
     }
 
+    #[allow(clippy::match_like_matches_macro)]
     fn eat_floating_suffix(&mut self) -> bool {
         let suffix_begin = self.byte_cursor;
         self.eat_ident_or_keyword();
-        match &self.src[suffix_begin..self.byte_cursor] {
+        match &*self.logical_str(suffix_begin) {
             "" => true,
             "f" | "F" | "l" | "L" => true,
             _ => false,
         }
     }
+
+    #[allow(clippy::match_like_matches_macro)]
     fn eat_integer_suffix(&mut self) -> bool {
         let suffix_begin = self.byte_cursor;
         self.eat_ident_or_keyword();
-        match &self.src[suffix_begin..self.byte_cursor] {
+        match &*self.logical_str(suffix_begin) {
             "" => true,
-            "u" | "U" | "l" | "L" | "ll" | "LL" => true,
+            "u" | "U" | "l" | "L" => true,
             "ul" | "uL" | "Ul" | "UL" => true,
             "lu" | "Lu" | "lU" | "LU" => true,
-            "ull" | "uLL" | "Ull" | "ULL" => true,
-            "llu" | "LLu" | "llU" | "LLU" => true,
+            "ll" | "LL" => self.dialect.has_long_long(),
+            "ull" | "uLL" | "Ull" | "ULL" => self.dialect.has_long_long(),
+            "llu" | "LLu" | "llU" | "LLU" => self.dialect.has_long_long(),
             _ => false,
         }
     }
 
     fn eat_decimal_digits(&mut self) -> bool {
-        let mut has_digits = false;
-        while let '0'..='9' = self.peek_char(0) {
-            has_digits = true;
-            self.bump_char();
-        }
-        has_digits
+        self.eat_digits(|c| c.is_ascii_digit())
     }
 
     fn eat_hexadecimal_digits(&mut self) -> bool {
-        let mut has_digits = false;
-        while let '0'..='9' | 'a'..='f' | 'A'..='F' = self.peek_char(0) {
-            has_digits = true;
-            self.bump_char();
-        }
-        has_digits
+        self.eat_digits(|c| c.is_ascii_hexdigit())
     }
 
-    fn eat_octal_digits(&mut self) -> bool {
+    fn eat_binary_digits(&mut self) -> bool {
+        self.eat_digits(|c| matches!(c, '0' | '1'))
+    }
+
+    /// Eats digits and the digit separators among them, which must come
+    /// between two digits, and returns `true` if there were any digits.
+    fn eat_digits(&mut self, is_digit: impl Fn(char) -> bool) -> bool {
         let mut has_digits = false;
-        while let '0'..='7' = self.peek_char(0) {
-            has_digits = true;
+        loop {
+            let c = self.peek_char(0);
+            if is_digit(c) {
+                has_digits = true;
+            } else if c == '\'' && self.is_digit_separator() {
+                if !is_digit(self.last_char) || !is_digit(self.peek_char(1)) {
+                    self.invalid_separator = true;
+                }
+            } else {
+                return has_digits;
+            }
             self.bump_char();
         }
-        has_digits
     }
 
+    /// Returns `true` if the `'` under the cursor is a digit separator
+    /// rather than the start of a character constant.
+    ///
+    /// As in a preprocessing number, it is one if followed by a digit,
+    /// a letter, `_` or another `'`.
+    fn is_digit_separator(&self) -> bool {
+        self.dialect.has_digit_separators()
+            && matches!(self.peek_char(1), '0'..='9' | 'a'..='z' | 'A'..='Z' | '_' | '\'')
+    }
+
+    /// Returns the `nth` char after the cursor without consuming it, or [`EOF`].
+    ///
+    /// Line splices are skipped over, as if they had been deleted in
+    /// translation phase 2.
     pub fn peek_char(&self, nth: usize) -> char {
-        self.remaining().chars().nth(nth).unwrap_or(EOF)
+        logical_chars(self.remaining(), self.trigraphs)
+            .nth(nth)
+            .unwrap_or(EOF)
     }
 
+    /// Consumes and returns the char under the cursor, together with
+    /// any line splices before it.
     pub fn bump_char(&mut self) -> Option<char> {
-        let mut chars = self.remaining().chars();
-
-        let c = chars.next()?;
-        self.byte_cursor += self.remaining().len() - chars.as_str().len();
-        self.char_cursor += 1;
+        let splice_len = splice_len(self.remaining(), self.trigraphs);
+        let (c, len) = physical_char(&self.remaining()[splice_len..], self.trigraphs)?;
+        if splice_len > 0 {
+            self.spliced = true;
+        }
+        let is_trigraph = len == 3 && c.is_ascii();
+        if is_trigraph {
+            self.has_trigraphs = true;
+        }
+        self.byte_cursor += splice_len + len;
+        self.char_cursor += splice_len + if is_trigraph { 3 } else { 1 };
+        self.last_char = c;
         Some(c)
     }
 
+    /// Returns the text from `begin` to the cursor as seen after
+    /// translation phase 2.
+    fn logical_str(&self, begin: usize) -> Cow<'a, str> {
+        logical_spelling(&self.src[begin..self.byte_cursor], self.trigraphs)
+    }
+
+    /// Returns the source text that has not been consumed yet.
     pub fn remaining(&self) -> &'a str {
         &self.src[self.byte_cursor..]
     }
 
+    /// Returns `true` if the whole source has been consumed.
     pub fn is_eof(&self) -> bool {
         self.remaining().is_empty()
     }
 }
 
+/// Section 5.2.1.1: returns the character the trigraph sequence at the
+/// beginning of `s` stands for, if any.
+pub(crate) fn trigraph(s: &str) -> Option<char> {
+    if !s.starts_with("??") {
+        return None;
+    }
+    match s[2..].chars().next()? {
+        '=' => Some('#'),
+        '(' => Some('['),
+        '/' => Some('\\'),
+        ')' => Some(']'),
+        '\'' => Some('^'),
+        '<' => Some('{'),
+        '!' => Some('|'),
+        '>' => Some('}'),
+        '-' => Some('~'),
+        _ => None,
+    }
+}
+
+/// Returns the first char of `s` as seen after translation phase 1,
+/// together with its length in bytes.
+///
+/// Trigraphs are the only multi-char sequences replaced in phase 1, so a
+/// length of 3 bytes for an ASCII char means that it was a trigraph.
+fn physical_char(s: &str, trigraphs: bool) -> Option<(char, usize)> {
+    if trigraphs {
+        if let Some(c) = trigraph(s) {
+            return Some((c, 3));
+        }
+    }
+    let c = s.chars().next()?;
+    Some((c, c.len_utf8()))
+}
+
+/// Splits a whitespace token after its first new-line, returning `None`
+/// if it has none, and no second part if the new-line ends the token.
+fn split_after_newline<'a>(token: &Token<'a>) -> Option<(Token<'a>, Option<Token<'a>>)> {
+    if token.kind != Whitespace {
+        return None;
+    }
+    let text = token.as_str();
+    let byte_len = text.find('\n')? + 1;
+    let byte_mid = token.byte_range.start + byte_len;
+    let char_mid = token.char_range.start + text[..byte_len].chars().count();
+    let head = Token::new(
+        Whitespace,
+        token.byte_range.start..byte_mid,
+        token.char_range.start..char_mid,
+        token.src,
+    );
+    let tail = match byte_mid < token.byte_range.end {
+        true => Some(Token::new(
+            Whitespace,
+            byte_mid..token.byte_range.end,
+            char_mid..token.char_range.end,
+            token.src,
+        )),
+        false => None,
+    };
+    Some((head, tail))
+}
+
+/// Returns the length of the line splices at the beginning of `s`,
+/// i.e. backslashes immediately followed by a new-line (Section 5.1.1.2).
+///
+/// Splices are ASCII, so the length is both in bytes and in chars.
+fn splice_len(s: &str, trigraphs: bool) -> usize {
+    let mut len = 0;
+    while let Some(('\\', backslash_len)) = physical_char(&s[len..], trigraphs) {
+        let rest = &s[len + backslash_len..];
+        if rest.starts_with('\n') {
+            len += backslash_len + 1;
+        } else if rest.starts_with("\r\n") {
+            len += backslash_len + 2;
+        } else {
+            break;
+        }
+    }
+    len
+}
+
+/// Maps `logical`, a byte range in the logical spelling of the text of
+/// `src` from `byte_begin`, back to byte and char ranges in `src`.
+pub(crate) fn source_ranges(
+    src: &str,
+    trigraphs: bool,
+    byte_begin: usize,
+    char_begin: usize,
+    logical: Range<usize>,
+) -> (Range<usize>, Range<usize>) {
+    let mut byte_cursor = byte_begin;
+    let mut char_cursor = char_begin;
+    let mut logical_cursor = 0;
+    let mut start = (byte_cursor, char_cursor);
+    while logical_cursor < logical.end {
+        let splice_len = splice_len(&src[byte_cursor..], trigraphs);
+        byte_cursor += splice_len;
+        char_cursor += splice_len;
+        if logical_cursor == logical.start {
+            start = (byte_cursor, char_cursor);
+        }
+        let (c, len) = match physical_char(&src[byte_cursor..], trigraphs) {
+            Some(physical) => physical,
+            None => break,
+        };
+        byte_cursor += len;
+        char_cursor += if len == 3 && c.is_ascii() { 3 } else { 1 };
+        logical_cursor += c.len_utf8();
+    }
+    (start.0..byte_cursor, start.1..char_cursor)
+}
+
+/// Iterates over the chars of `s` as seen after translation phase 2.
+fn logical_chars(mut s: &str, trigraphs: bool) -> impl Iterator<Item = char> + '_ {
+    std::iter::from_fn(move || {
+        s = &s[splice_len(s, trigraphs)..];
+        let (c, len) = physical_char(s, trigraphs)?;
+        s = &s[len..];
+        Some(c)
+    })
+}
+
+/// Replaces trigraphs, if enabled, and removes line splices from `s`.
+pub(crate) fn logical_spelling(s: &str, trigraphs: bool) -> Cow<'_, str> {
+    let is_clean = match trigraphs {
+        true => !s.contains('\\') && !s.contains("??"),
+        false => !s.contains('\\'),
+    };
+    match is_clean {
+        true => Cow::Borrowed(s),
+        false => Cow::Owned(logical_chars(s, trigraphs).collect()),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::diagnostics::Severity;
+
+    /// Takes the diagnostics of `lexer` as codes and byte ranges.
+    fn diagnostics(lexer: &mut Lexer) -> Vec<(&'static str, Range<usize>)> {
+        let diagnostics = lexer.take_diagnostics().into_iter();
+        diagnostics
+            .map(|d| (d.code.unwrap(), d.primary.byte_range))
+            .collect()
+    }
 
     #[test]
     fn test_eof() {
@@ -424,12 +1294,724 @@ Some(
             tokens,
             r#"
 [
-    [Keyword@0..3],
-    [Keyword@4..9],
-    [Keyword@10..12],
-    [Keyword@13..16],
+    [Keyword(Int)@0..3],
+    [Keyword(Float)@4..9],
+    [Keyword(If)@10..12],
+    [Keyword(For)@13..16],
+]"#
+        );
+    }
+
+    #[test]
+    fn test_hexadecimal_floating_consts() {
+        let tokens: Vec<Token> =
+            Lexer::new("0x1.8p-3 0x.8p1 0X1.P+0 0x1p10 0xA.Bp2f 0x1.8P-3L 0x0.1p0F 0xffp-1l")
+                .iter()
+                .collect();
+        assert_debug_snapshot!(
+            tokens,
+            r#"
+[
+    [Const(Float)@0..8],
+    [Const(Float)@9..15],
+    [Const(Float)@16..23],
+    [Const(Float)@24..30],
+    [Const(Float)@31..39],
+    [Const(Float)@40..49],
+    [Const(Float)@50..58],
+    [Const(Float)@59..67],
+]"#
+        );
+    }
+
+    #[test]
+    fn test_hexadecimal_floating_errors() {
+        let tokens: Vec<Token> = Lexer::new("0x1.8 0x1p 0x1.8p+ 0x.p1 0x1p1u")
+            .iter()
+            .collect();
+        assert_debug_snapshot!(
+            tokens,
+            r#"
+[
+    [Error(MissingBinaryExponent)@0..5],
+    [Error(MissingBinaryExponent)@6..10],
+    [Error(MissingBinaryExponent)@11..18],
+    [Error(NoHexadecimalDigits)@19..24],
+    [Error(InvalidFloatingSuffix)@25..31],
+]"#
+        );
+    }
+
+    #[test]
+    fn test_universal_character_names() {
+        let tokens: Vec<Token> = Lexer::new(r"\u53d8量 a\U000000E9 \u0041 \u12 a\u3002 \u0660")
+            .iter()
+            .collect();
+        assert_debug_snapshot!(
+            tokens,
+            r#"
+[
+    [Ident@0..9],
+    [Ident@10..21],
+    [Error(InvalidUniversalCharacterName)@22..28],
+    [Error(InvalidUniversalCharacterName)@29..33],
+    [Error(InvalidIdentifierCharacter)@34..41],
+    [Error(InvalidIdentifierCharacter)@42..48],
+]"#
+        );
+    }
+
+    #[test]
+    fn test_unicode_identifiers() {
+        let src = "int 变量 = 1; ɐ";
+        let kinds = |dialect| {
+            Lexer::new(src)
+                .with_dialect(dialect)
+                .iter()
+                .map(|token| token.kind)
+                .collect::<Vec<_>>()
+        };
+        assert_debug_snapshot!(
+            kinds(Dialect::C89),
+            r#"
+[
+    Keyword(
+        Int,
+    ),
+    Error(
+        UnexpectedCharacter,
+    ),
+    Error(
+        UnexpectedCharacter,
+    ),
+    Punct(
+        Equal,
+    ),
+    Const(
+        Integer,
+    ),
+    Punct(
+        Semi,
+    ),
+    Error(
+        UnexpectedCharacter,
+    ),
+]"#
+        );
+        assert_eq!(kinds(Dialect::C99), kinds(Dialect::C11));
+        assert_debug_snapshot!(
+            kinds(Dialect::C23),
+            r#"
+[
+    Keyword(
+        Int,
+    ),
+    Ident,
+    Punct(
+        Equal,
+    ),
+    Const(
+        Integer,
+    ),
+    Punct(
+        Semi,
+    ),
+    Ident,
+]"#
+        );
+    }
+
+    #[test]
+    fn test_dialect_keywords() {
+        let src = "inline _Atomic _Generic bool nullptr typeof";
+        let keywords = |dialect| {
+            Lexer::new(src)
+                .with_dialect(dialect)
+                .iter()
+                .filter(|token| matches!(token.kind, Keyword(_)))
+                .count()
+        };
+        assert_eq!(keywords(Dialect::C89), 0);
+        assert_eq!(keywords(Dialect::C99), 1);
+        assert_eq!(keywords(Dialect::C11), 3);
+        assert_eq!(keywords(Dialect::C17), 3);
+        assert_eq!(keywords(Dialect::C23), 6);
+    }
+
+    #[test]
+    fn test_keyword_table() {
+        for &keyword in KeywordKind::ALL {
+            assert_eq!(keyword.as_str().parse(), Ok(keyword));
+            let tokens: Vec<Token> = Lexer::new(keyword.as_str())
+                .with_dialect(keyword.since())
+                .iter()
+                .collect();
+            assert_eq!(tokens[0].kind, Keyword(keyword));
+        }
+        assert_eq!("main".parse::<KeywordKind>(), Err(()));
+    }
+
+    #[test]
+    fn test_c89_forms() {
+        let tokens: Vec<Token> = Lexer::new("// <: 1ll 0x1p2")
+            .with_dialect(Dialect::C89)
+            .iter()
+            .collect();
+        assert_debug_snapshot!(
+            tokens,
+            r#"
+[
+    [Punct(Slash)@0..1],
+    [Punct(Slash)@1..2],
+    [Punct(Less)@3..4],
+    [Punct(Colon)@4..5],
+    [Error(InvalidIntegerSuffix)@6..9],
+    [Error(InvalidIntegerSuffix)@10..15],
+]"#
+        );
+    }
+
+    #[test]
+    fn test_c23_punctuators() {
+        let tokens: Vec<Token> = Lexer::new("[[gnu::packed]]")
+            .with_dialect(Dialect::C23)
+            .iter()
+            .collect();
+        assert_debug_snapshot!(
+            tokens,
+            r#"
+[
+    [Punct(LBracket)@0..1],
+    [Punct(LBracket)@1..2],
+    [Ident@2..5],
+    [Punct(ColonColon)@5..7],
+    [Ident@7..13],
+    [Punct(RBracket)@13..14],
+    [Punct(RBracket)@14..15],
+]"#
+        );
+    }
+
+    #[test]
+    fn test_line_splices() {
+        let src = "in\\\nt \\\r\n+\\\n= \"a\\\nb\" \\ x";
+        let tokens: Vec<Token> = Lexer::new(src).iter().collect();
+        assert_debug_snapshot!(
+            tokens,
+            r#"
+[
+    [Keyword(Int)@0..5],
+    [Punct(PlusEqual)@9..13],
+    [StrLit(Plain)@14..20],
+    [Error(UnknownPunctuator)@21..22],
+    [Ident@23..24],
+]"#
+        );
+        let spellings: Vec<_> = tokens.iter().map(|token| token.spelling()).collect();
+        assert_eq!(spellings, ["int", "+=", "\"ab\"", "\\", "x"]);
+        assert!(tokens[0].spliced && !tokens[4].spliced);
+    }
+
+    #[test]
+    fn test_spliced_line_comment() {
+        let mut lexer = Lexer::new("// comment \\\n continued\nint");
+        assert_debug_snapshot!(
+            lexer.advance_token(),
+            r#"
+Some(
+    [Comment@0..23],
+)"#
+        );
+    }
+
+    #[test]
+    fn test_layout_flags() {
+        let src = "#define F(x)\n  #define G (x)/**/y+\\\nz /*\n*/ w\n";
+        let lexer = Lexer::new(src).with_mode(LexMode::Preprocessing);
+        let layout: Vec<_> = lexer
+            .iter()
+            .map(|t| (t.spelling(), t.at_line_start, t.has_leading_space))
+            .collect();
+        assert_eq!(
+            layout,
+            [
+                ("#".into(), true, false),
+                ("define".into(), false, false),
+                ("F".into(), false, true),
+                ("(".into(), false, false),
+                ("x".into(), false, false),
+                (")".into(), false, false),
+                ("#".into(), true, true),
+                ("define".into(), false, false),
+                ("G".into(), false, true),
+                ("(".into(), false, true),
+                ("x".into(), false, false),
+                (")".into(), false, false),
+                ("y".into(), false, true),
+                ("+".into(), false, false),
+                ("z".into(), false, false),
+                ("w".into(), false, true),
+            ]
+        );
+
+        // The new-line ending an unterminated literal, or one after a line
+        // splice within whitespace, still ends the line.
+        let src = "char *s = \"abc\n#define X 1 \\\n  '\n# x";
+        let lexer = Lexer::new(src).with_mode(LexMode::Preprocessing);
+        let starts: Vec<_> = lexer
+            .iter()
+            .filter(|t| t.at_line_start)
+            .map(|t| t.spelling())
+            .collect();
+        assert_eq!(starts, ["char", "#", "#"]);
+    }
+
+    #[test]
+    fn test_lossless() {
+        let src = "/* a */ int x; // b\n\n  y\\\n = 1 /* c\n d */ ;  \n";
+        let tokens: Vec<LosslessToken> = Lexer::new(src).lossless().collect();
+        let trivia =
+            |tokens: &[Token<'static>]| tokens.iter().map(|t| t.as_str()).collect::<Vec<_>>();
+        let layout: Vec<_> = tokens
+            .iter()
+            .map(|t| {
+                let token = t.token.as_ref().map(|token| token.as_str());
+                (trivia(&t.leading), token, trivia(&t.trailing))
+            })
+            .collect();
+        assert_eq!(
+            layout,
+            [
+                (vec!["/* a */", " "], Some("int"), vec![" "]),
+                (vec![], Some("x"), vec![]),
+                (vec![], Some(";"), vec![" ", "// b", "\n"]),
+                (vec!["\n  "], Some("y"), vec!["\\\n", " "]),
+                (vec![], Some("="), vec![" "]),
+                (vec![], Some("1"), vec![" ", "/* c\n d */", " "]),
+                (vec![], Some(";"), vec!["  \n"]),
+                (vec![], None, vec![]),
+            ]
+        );
+
+        let tokens: Vec<LosslessToken> = Lexer::new("x = \"abc\n    y;").lossless().collect();
+        assert_eq!(tokens[2].token.as_ref().unwrap().as_str(), "\"abc\n");
+        assert!(tokens[2].trailing.is_empty());
+        assert_eq!(trivia(&tokens[3].leading), ["    "]);
+
+        for src in [
+            "",
+            "  \n\t",
+            "// only a comment",
+            "#define A 1 /* x\n */\n\u{3000}A\r\n@ \"unterminated\n/* unclosed",
+            "??=include <a.h>\n??/\nint",
+        ] {
+            let lexer = Lexer::new(src).with_trigraphs(true);
+            let text: String = lexer.lossless().map(|t| t.to_string()).collect();
+            assert_eq!(text, src);
+        }
+    }
+
+    #[test]
+    fn test_trigraphs() {
+        let src = "??=define X(a) a ??( ??) ??'??/\n= ??!??! \"??-\"";
+        let tokens: Vec<Token> = Lexer::new(src).with_trigraphs(true).iter().collect();
+        assert_eq!(tokens[0].kind, Directive(DirectiveKind::Define));
+        let spellings: Vec<_> = tokens.iter().map(|token| token.spelling()).collect();
+        assert_eq!(
+            spellings,
+            ["#define", "X", "(", "a", ")", "a", "[", "]", "^=", "||", "\"~\""]
+        );
+        assert!(tokens[1..].iter().all(|token| token.in_directive));
+
+        let tokens: Vec<Token> = Lexer::new(src)
+            .with_mode(LexMode::Preprocessing)
+            .with_trigraphs(true)
+            .iter()
+            .collect();
+        let spellings: Vec<_> = tokens.iter().map(|token| token.spelling()).collect();
+        assert_eq!(
+            spellings,
+            ["#", "define", "X", "(", "a", ")", "a", "[", "]", "^=", "||", "\"~\""]
+        );
+        assert_debug_snapshot!(tokens[9], "[Punct(CaretEqual)@25..33]");
+        assert!(tokens[9].spliced && tokens[9].has_trigraphs);
+
+        let tokens: Vec<Token> = Lexer::new(src).iter().collect();
+        assert_eq!(tokens[0].spelling(), "?");
+    }
+
+    #[test]
+    fn test_trigraph_warnings() {
+        let mut lexer = Lexer::new(r#""a??!b??" '??='"#);
+        while lexer.advance_token().is_some() {}
+        assert_eq!(diagnostics(&mut lexer), [(TrigraphIgnored.code(), 2..5)]);
+
+        let mut lexer = Lexer::new(r#""??=??/"" "#).with_trigraphs(true);
+        assert_debug_snapshot!(lexer.advance_token(), "Some(\n    [StrLit(Plain)@0..9],\n)");
+        assert_eq!(lexer.take_diagnostics().len(), 2);
+        assert!(lexer.take_diagnostics().is_empty());
+    }
+
+    #[test]
+    fn test_float_warnings() {
+        let mut lexer = Lexer::new("1e309 1e-400 1e39f 1e309L 1e-400L");
+        while lexer.advance_token().is_some() {}
+        let codes: Vec<_> = diagnostics(&mut lexer).into_iter().map(|d| d.0).collect();
+        let kinds = [FloatOverflow, FloatUnderflow, FloatOverflow];
+        assert_eq!(codes, kinds.map(WarningKind::code));
+
+        let mut lexer = Lexer::new("1e309L").with_data_model(DataModel::LLP64);
+        while lexer.advance_token().is_some() {}
+        let warnings = lexer.take_diagnostics();
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(warnings[0].severity, Severity::Warning);
+        assert_eq!(warnings[0].code, Some(FloatOverflow.code()));
+        assert_eq!(warnings[0].primary.char_range, 0..6);
+    }
+
+    #[test]
+    fn test_escape_errors() {
+        let tokens: Vec<Token> = Lexer::new(r#""\x" '\x100' "\400" L'\u0041' "\n\x7f""#)
+            .iter()
+            .collect();
+        assert_debug_snapshot!(
+            tokens,
+            r#"
+[
+    [Error(EmptyHexEscape)@0..4],
+    [Error(HexEscapeOutOfRange)@5..12],
+    [Error(OctalEscapeOutOfRange)@13..19],
+    [Error(InvalidUniversalCharacterName)@20..29],
+    [StrLit(Plain)@30..38],
 ]"#
         );
+
+        let tokens: Vec<Token> = Lexer::new(r"u8'a' u8'ab' u8'\u00e9'")
+            .with_dialect(Dialect::C23)
+            .iter()
+            .collect();
+        assert_debug_snapshot!(
+            tokens,
+            r#"
+[
+    [Const(Char(Utf8))@0..5],
+    [Error(Utf8CharacterTooLarge)@6..12],
+    [Error(Utf8CharacterTooLarge)@13..23],
+]"#
+        );
+    }
+
+    #[test]
+    fn test_unknown_escape_warnings() {
+        let mut lexer = Lexer::new("'\\q' \"a\\\\\n%\"");
+        while lexer.advance_token().is_some() {}
+        let code = UnknownEscapeSequence.code();
+        assert_eq!(diagnostics(&mut lexer), [(code, 1..3), (code, 7..11)]);
+
+        // Universal character names are unknown escape sequences in C89.
+        let mut lexer = Lexer::new("'\\u00e9'").with_dialect(Dialect::C89);
+        assert_eq!(
+            lexer.advance_token().unwrap().kind,
+            Const(Char(Encoding::Plain))
+        );
+        assert_eq!(diagnostics(&mut lexer), [(code, 1..3)]);
+    }
+
+    #[test]
+    fn test_octal_constants() {
+        let tokens: Vec<Token> = Lexer::new("0755 089 0758 09.5 08e1 0 0b1 09u")
+            .iter()
+            .collect();
+        assert_debug_snapshot!(
+            tokens,
+            r#"
+[
+    [Const(Integer)@0..4],
+    [Error(InvalidOctalDigit)@5..8],
+    [Error(InvalidOctalDigit)@9..13],
+    [Const(Float)@14..18],
+    [Const(Float)@19..23],
+    [Const(Integer)@24..25],
+    [Error(InvalidIntegerSuffix)@26..29],
+    [Error(InvalidOctalDigit)@30..33],
+]"#
+        );
+        assert_eq!(tokens[1].error_range(), (6..7, 6..7));
+        assert_eq!(tokens[2].error_range(), (12..13, 12..13));
+    }
+
+    #[test]
+    fn test_binary_constants() {
+        let src = "0b1010 0B1u 0b 0b102 0b1z";
+        let tokens: Vec<Token> = Lexer::new(src).with_dialect(Dialect::C23).iter().collect();
+        assert_debug_snapshot!(
+            tokens,
+            r#"
+[
+    [Const(Integer)@0..6],
+    [Const(Integer)@7..11],
+    [Error(NoBinaryDigits)@12..14],
+    [Error(InvalidBinaryDigit)@15..20],
+    [Error(InvalidIntegerSuffix)@21..25],
+]"#
+        );
+    }
+
+    #[test]
+    fn test_digit_separators() {
+        let src = "1'000'000 0x'1 0'17 0xFF'FFu 1'5.2'5e1'0f 0b1'0 1''0 1'a 1'";
+        let tokens: Vec<Token> = Lexer::new(src).with_dialect(Dialect::C23).iter().collect();
+        assert_debug_snapshot!(
+            tokens,
+            r#"
+[
+    [Const(Integer)@0..9],
+    [Error(InvalidDigitSeparator)@10..14],
+    [Const(Integer)@15..19],
+    [Const(Integer)@20..28],
+    [Const(Float)@29..41],
+    [Const(Integer)@42..47],
+    [Error(InvalidDigitSeparator)@48..52],
+    [Error(InvalidDigitSeparator)@53..56],
+    [Const(Integer)@57..58],
+    [Error(UnterminatedChar)@58..59],
+]"#
+        );
+        let kinds: Vec<TokenKind> = Lexer::new("1'000'000").iter().map(|t| t.kind).collect();
+        assert_eq!(
+            kinds,
+            [Const(Integer), Const(Char(Encoding::Plain)), Const(Integer)]
+        );
+    }
+
+    #[test]
+    fn test_preprocessing_tokens() {
+        let src = "#include <stdio.h>\n#include \"a.h\"\nint x = 1.2.3e+xy<y>@0x1p-3;";
+        let tokens: Vec<Token> = Lexer::new(src)
+            .with_mode(LexMode::Preprocessing)
+            .iter()
+            .collect();
+        assert_debug_snapshot!(
+            tokens,
+            r#"
+[
+    [Punct(Hash)@0..1],
+    [Ident@1..8],
+    [HeaderName@9..18],
+    [Punct(Hash)@19..20],
+    [Ident@20..27],
+    [HeaderName@28..33],
+    [Ident@34..37],
+    [Ident@38..39],
+    [Punct(Equal)@40..41],
+    [PpNumber@42..51],
+    [Punct(Less)@51..52],
+    [Ident@52..53],
+    [Punct(Greater)@53..54],
+    [Other@54..55],
+    [PpNumber@55..61],
+    [Punct(Semi)@61..62],
+]"#
+        );
+    }
+
+    #[test]
+    fn test_directives() {
+        let src = "#include <stdio.h>  \n  # /* a */ define X(a) \\\n  a /* b\n */ + 1 // c\n\
+                   #\n%:if 0\nx # define\n## y\n#define Y /* d";
+        let tokens: Vec<Token> = Lexer::new(src).iter().collect();
+        let spellings: Vec<_> = tokens
+            .iter()
+            .map(|token| (token.spelling(), token.in_directive))
+            .collect();
+        assert_eq!(
+            spellings,
+            [
+                ("#include".into(), false),
+                ("<".into(), true),
+                ("stdio".into(), true),
+                (".".into(), true),
+                ("h".into(), true),
+                (">".into(), true),
+                ("# /* a */ define".into(), false),
+                ("X".into(), true),
+                ("(".into(), true),
+                ("a".into(), true),
+                (")".into(), true),
+                ("a".into(), true),
+                ("+".into(), true),
+                ("1".into(), true),
+                ("#".into(), false),
+                ("%:if".into(), false),
+                ("0".into(), true),
+                ("x".into(), false),
+                ("#".into(), false),
+                ("define".into(), false),
+                ("##".into(), false),
+                ("y".into(), false),
+                ("#define".into(), false),
+                ("Y".into(), true),
+                ("/* d".into(), true),
+            ] as [(Cow<str>, bool); 25]
+        );
+        let kinds: Vec<_> = tokens.iter().map(|token| token.kind).collect();
+        assert_eq!(
+            [kinds[0], kinds[6], kinds[14], kinds[15], kinds[22]],
+            [
+                Directive(DirectiveKind::Include),
+                Directive(DirectiveKind::Define),
+                Directive(DirectiveKind::Null),
+                Directive(DirectiveKind::If),
+                Directive(DirectiveKind::Define),
+            ]
+        );
+        assert_eq!(kinds[24], Error(UnclosedBlockComment));
+        let tokens: Vec<Token> = Lexer::new(
+            "# 123 x
+#foo",
+        )
+        .iter()
+        .collect();
+        assert_eq!(tokens[0].kind, Directive(DirectiveKind::NonDirective));
+        assert_eq!(tokens[2].kind, Directive(DirectiveKind::NonDirective));
+        let tokens: Vec<Token> = Lexer::new("# \"x\"\n# // y\n#").iter().collect();
+        let kinds: Vec<_> = tokens.iter().map(|token| token.kind).collect();
+        assert_eq!(
+            kinds,
+            [
+                Directive(DirectiveKind::NonDirective),
+                StrLit(Encoding::Plain),
+                Directive(DirectiveKind::Null),
+                Directive(DirectiveKind::Null),
+            ]
+        );
+
+        // Problems within directives are still reported.
+        let src = "#define S \"abc\n#define N 0x\n#define F 1e999\n#error don't\n#";
+        let mut lexer = Lexer::new(src);
+        while lexer.advance_token().is_some() {}
+        let codes: Vec<_> = lexer
+            .take_diagnostics()
+            .iter()
+            .map(|diagnostic| diagnostic.code.unwrap())
+            .collect();
+        assert_eq!(codes, ["E0002", "E0008", "W0003", "E0003"]);
+        let kinds: Vec<_> = Lexer::new(src).iter().map(|token| token.kind).collect();
+        assert_eq!(
+            kinds
+                .iter()
+                .filter(|kind| matches!(kind, Directive(_)))
+                .count(),
+            5
+        );
+
+        let tokens: Vec<Token> = Lexer::new("#warning w\n#elifdef X").iter().collect();
+        assert_eq!(tokens[0].kind, Directive(DirectiveKind::NonDirective));
+        let tokens: Vec<Token> = Lexer::new("#warning w\n#elifdef X")
+            .with_dialect(Dialect::C23)
+            .iter()
+            .collect();
+        assert_eq!(tokens[0].kind, Directive(DirectiveKind::Warning));
+        assert_eq!(tokens[2].kind, Directive(DirectiveKind::Elifdef));
+    }
+
+    #[test]
+    fn test_line_markers() {
+        let src = "??=line 10 \"x.c\"\n@\n#line 0x20\n#line 7 \\\n \"y.c\" // c\n@";
+        let mut lexer = Lexer::new(src).with_trigraphs(true);
+        let mut markers = Vec::new();
+        let mut ready = Vec::new();
+        while let Some(token) = lexer.advance_token() {
+            // The marker of a directive is ready by the time its line ends.
+            if token.as_str() == "@" {
+                ready.push(markers.len());
+            }
+            markers.append(&mut lexer.take_line_markers());
+        }
+        let marker = |line, presumed_line, path: &str| LineMarker {
+            line,
+            presumed_line,
+            path: Some(path.to_string()),
+        };
+        assert_eq!(markers, [marker(1, 10, "x.c"), marker(5, 7, "y.c")]);
+        assert_eq!(ready, [1, 2]);
+    }
+
+    #[test]
+    fn test_unclosed_header_names() {
+        let src = "#include <stdio.h\n# /**/ include \"a.h";
+        let kinds: Vec<TokenKind> = Lexer::new(src)
+            .with_mode(LexMode::Preprocessing)
+            .iter()
+            .map(|token| token.kind)
+            .collect();
+        assert_eq!(
+            kinds,
+            [
+                Punct(PunctKind::Hash),
+                Ident,
+                Punct(PunctKind::Less),
+                Ident,
+                Punct(PunctKind::Dot),
+                Ident,
+                Punct(PunctKind::Hash),
+                Ident,
+                Error(UnterminatedString),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_convert_tokens() {
+        let src = "int 1.2.3 0x1p-3 '\\q' @ 1e+xy <a.h>";
+        let mut lexer = Lexer::new(src).with_mode(LexMode::Preprocessing);
+        let mut tokens = Vec::new();
+        while let Some(token) = lexer.advance_token() {
+            if !token.kind.is_trivia() {
+                tokens.push(lexer.convert_token(&token));
+            }
+        }
+        assert_debug_snapshot!(
+            tokens,
+            r#"
+[
+    [Keyword(Int)@0..3],
+    [Error(InvalidNumber)@4..9],
+    [Const(Float)@10..16],
+    [Const(Char(Plain))@17..21],
+    [Error(UnknownPunctuator)@22..23],
+    [Error(InvalidFloatingSuffix)@24..29],
+    [Punct(Less)@30..31],
+    [Ident@31..32],
+    [Punct(Dot)@32..33],
+    [Ident@33..34],
+    [Punct(Greater)@34..35],
+]"#
+        );
+        let codes: Vec<_> = diagnostics(&mut lexer).into_iter().map(|d| d.0).collect();
+        assert_eq!(codes, ["E0019", "W0005", "E0004", "E0007"]);
+    }
+
+    #[test]
+    fn test_punctuators() {
+        let src = "[ ] ( ) { } . -> ++ -- & * + - ~ ! / % << >> < > <= >= == != ^ | && || \
+                   ? : ; ... = *= /= %= += -= <<= >>= &= ^= |= , # ##";
+        let tokens: Vec<Token> = Lexer::new(src).iter().collect();
+        let canonical: Vec<_> = tokens
+            .iter()
+            .filter_map(|token| token.canonical_spelling())
+            .collect();
+        assert_eq!(canonical, src.split_whitespace().collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_digraphs() {
+        let tokens: Vec<Token> = Lexer::new("<: :> <% %> %: %:%: -> ").iter().collect();
+        let canonical: Vec<_> = tokens
+            .iter()
+            .filter_map(|token| token.canonical_spelling())
+            .collect();
+        assert_eq!(canonical, ["[", "]", "{", "}", "#", "##", "->"]);
     }
 
     #[test]
@@ -439,10 +2021,57 @@ Some(
             tokens,
             r#"
 [
-    [Const(Char)@0..3],
-    [Const(Char)@4..8],
-    [Const(Char)@9..13],
+    [Const(Char(Plain))@0..3],
+    [Const(Char(Plain))@4..8],
+    [Const(Char(Plain))@9..13],
 ]"#
         );
     }
+
+    #[test]
+    fn test_encoding_prefixes() {
+        let src = r#"L"a" u8"a" u"a" U"a" L'a' u'a' U'a' u8'a'"#;
+        let tokens: Vec<Token> = Lexer::new(src).with_dialect(Dialect::C23).iter().collect();
+        assert_debug_snapshot!(
+            tokens,
+            r#"
+[
+    [StrLit(Wide)@0..4],
+    [StrLit(Utf8)@5..10],
+    [StrLit(Utf16)@11..15],
+    [StrLit(Utf32)@16..20],
+    [Const(Char(Wide))@21..25],
+    [Const(Char(Utf16))@26..30],
+    [Const(Char(Utf32))@31..35],
+    [Const(Char(Utf8))@36..41],
+]"#
+        );
+
+        let kinds = |dialect| -> Vec<TokenKind> {
+            Lexer::new(r#"u8"a" u8'a' L'a'"#)
+                .with_dialect(dialect)
+                .iter()
+                .map(|token| token.kind)
+                .collect()
+        };
+        assert_eq!(
+            kinds(Dialect::C99),
+            [
+                Ident,
+                StrLit(Encoding::Plain),
+                Ident,
+                Const(Char(Encoding::Plain)),
+                Const(Char(Encoding::Wide)),
+            ]
+        );
+        assert_eq!(
+            kinds(Dialect::C11),
+            [
+                StrLit(Encoding::Utf8),
+                Ident,
+                Const(Char(Encoding::Plain)),
+                Const(Char(Encoding::Wide)),
+            ]
+        );
+    }
 }
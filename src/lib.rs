@@ -0,0 +1,56 @@
+//! A C99-compatible lexer written in Rust.
+//!
+//! The crate is organised around a handful of types:
+//!
+//! - [`SourceFile`] loads a source file and maps character offsets back to
+//!   lines and columns;
+//! - [`Lexer`] turns the source text into a stream of [`Token`]s;
+//! - [`TokenKind`] and [`ErrorKind`] classify each token, including the
+//!   erroneous ones, so that a single pass can report every lexical error;
+//! - [`Diagnostic`] describes an error or a warning, which an [`Emitter`]
+//!   reports;
+//! - [`Statistics`] summarises a token stream;
+//! - [`literal`] evaluates the values of constants for a [`DataModel`];
+//! - [`concat_strings`] joins adjacent string literals;
+//! - [`Preprocessor`] executes directives and expands macros, keeping the
+//!   expansion history of each token in its [`Location`].
+//!
+//! ```
+//! use clex::{KeywordKind, Lexer, TokenKind};
+//!
+//! let kinds: Vec<TokenKind> = Lexer::new("int x = 0;").iter().map(|t| t.kind).collect();
+//! assert_eq!(kinds[0], TokenKind::Keyword(KeywordKind::Int));
+//! ```
+
+#[cfg(test)]
+#[macro_use]
+mod test_utils;
+
+pub mod concat;
+pub mod diagnostics;
+pub mod dialect;
+pub mod lexer;
+pub mod literal;
+pub mod locale;
+pub mod preprocess;
+pub mod source;
+pub mod stats;
+pub mod token;
+mod unicode;
+
+pub use concat::{concat_strings, Concatenated, StringLiteral};
+pub use diagnostics::{explain, Diagnostic, Emitter, HumanEmitter, Label, Severity};
+pub use dialect::Dialect;
+pub use lexer::{LexMode, Lexer};
+pub use literal::{
+    DataModel, FloatFormat, FloatStatus, FloatType, FloatValue, IntegerType, IntegerValue,
+    LiteralError,
+};
+pub use locale::{Catalog, Language};
+pub use preprocess::{Location, PpError, PpErrorKind, PpToken, Preprocessor};
+pub use source::SourceFile;
+pub use stats::Statistics;
+pub use token::{
+    ConstKind, DirectiveKind, Encoding, ErrorKind, KeywordKind, LosslessToken, PunctClass,
+    PunctKind, Token, TokenKind, WarningKind,
+};
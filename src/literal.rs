@@ -0,0 +1,1150 @@
+//! Evaluation of the values of constants.
+
+use crate::dialect::Dialect;
+use crate::token::*;
+use crate::unicode;
+use std::cmp::Ordering;
+use std::error;
+use std::fmt;
+use std::ops::Range;
+use std::str::FromStr;
+
+/// The data model of the target, deciding the widths of the integer types.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum DataModel {
+    /// `int`, `long` and pointers are 32-bit, e.g. 32-bit Linux and Windows
+    ILP32,
+    /// `long` and pointers are 64-bit, e.g. 64-bit Linux and macOS
+    #[default]
+    LP64,
+    /// only pointers are 64-bit, e.g. 64-bit Windows
+    LLP64,
+}
+
+impl DataModel {
+    /// Returns the width of `ty` in bits.
+    pub fn width(self, ty: IntegerType) -> u32 {
+        use IntegerType::*;
+        match (self, ty) {
+            (_, Int) | (_, UnsignedInt) => 32,
+            (DataModel::LP64, Long) | (DataModel::LP64, UnsignedLong) => 64,
+            (_, Long) | (_, UnsignedLong) => 32,
+            (_, LongLong) | (_, UnsignedLongLong) => 64,
+        }
+    }
+
+    /// Returns the largest value representable in `ty`.
+    pub fn max_value(self, ty: IntegerType) -> u128 {
+        match ty.is_signed() {
+            true => (1 << (self.width(ty) - 1)) - 1,
+            false => (1 << self.width(ty)) - 1,
+        }
+    }
+
+    /// Returns the width of `wchar_t` in bits: 16 on Windows, 32 elsewhere.
+    pub fn wchar_width(self) -> u32 {
+        match self {
+            DataModel::LLP64 => 16,
+            _ => 32,
+        }
+    }
+
+    /// Returns the format of `long double`, assuming an x86 target:
+    /// Windows makes it the same as `double`, others use the x87 format.
+    pub fn long_double_format(self) -> FloatFormat {
+        match self {
+            DataModel::LLP64 => FloatFormat::Binary64,
+            _ => FloatFormat::X87Extended,
+        }
+    }
+}
+
+impl FromStr for DataModel {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "ilp32" => Ok(DataModel::ILP32),
+            "lp64" => Ok(DataModel::LP64),
+            "llp64" => Ok(DataModel::LLP64),
+            _ => Err(format!("unknown data model `{}`", s)),
+        }
+    }
+}
+
+impl fmt::Display for DataModel {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            DataModel::ILP32 => "ilp32",
+            DataModel::LP64 => "lp64",
+            DataModel::LLP64 => "llp64",
+        };
+        f.write_str(name)
+    }
+}
+
+/// The types an integer constant may have (Section 6.4.4.1).
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum IntegerType {
+    Int,
+    UnsignedInt,
+    Long,
+    UnsignedLong,
+    LongLong,
+    UnsignedLongLong,
+}
+
+impl IntegerType {
+    pub fn is_signed(self) -> bool {
+        use IntegerType::*;
+        matches!(self, Int | Long | LongLong)
+    }
+
+    /// Returns the name of the type in C.
+    pub fn as_str(self) -> &'static str {
+        use IntegerType::*;
+        match self {
+            Int => "int",
+            UnsignedInt => "unsigned int",
+            Long => "long",
+            UnsignedLong => "unsigned long",
+            LongLong => "long long",
+            UnsignedLongLong => "unsigned long long",
+        }
+    }
+}
+
+/// The value of an integer constant, together with its type.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct IntegerValue {
+    pub value: u128,
+    pub ty: IntegerType,
+}
+
+/// The reasons why a constant cannot be evaluated.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LiteralError {
+    /// The token is not a valid constant of the requested kind
+    InvalidLiteral,
+    /// The value of an integer constant fits in none of its candidate types
+    IntegerTooLarge,
+    /// A `\x` escape sequence has no digits
+    EmptyHexEscape,
+    /// The value of a hexadecimal escape sequence does not fit in a code unit
+    HexEscapeOutOfRange,
+    /// The value of an octal escape sequence does not fit in a code unit
+    OctalEscapeOutOfRange,
+    /// A `\u` or `\U` escape sequence is malformed or names a forbidden character
+    InvalidUniversalCharacterName,
+    /// Adjacent string literals have different encoding prefixes
+    IncompatibleEncodings,
+    /// A `u8` character constant does not fit in a single code unit
+    Utf8CharacterTooLarge,
+}
+
+impl fmt::Display for LiteralError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LiteralError::InvalidLiteral => write!(f, "invalid constant"),
+            LiteralError::IntegerTooLarge => write!(f, "integer constant is too large"),
+            LiteralError::EmptyHexEscape => write!(f, "\\x used with no following hex digits"),
+            LiteralError::HexEscapeOutOfRange => write!(f, "hex escape sequence out of range"),
+            LiteralError::OctalEscapeOutOfRange => {
+                write!(f, "octal escape sequence out of range")
+            }
+            LiteralError::InvalidUniversalCharacterName => {
+                write!(f, "invalid universal character name")
+            }
+            LiteralError::IncompatibleEncodings => {
+                write!(f, "concatenating string literals with different encodings")
+            }
+            LiteralError::Utf8CharacterTooLarge => {
+                write!(f, "UTF-8 character constant does not fit in a code unit")
+            }
+        }
+    }
+}
+
+impl error::Error for LiteralError {}
+
+/// Evaluates the spelling of an integer constant, as produced by the lexer.
+///
+/// The type is the first one of the list in Section 6.4.4.1 in which the
+/// value can be represented, according to `model`.
+pub fn integer_value(spelling: &str, model: DataModel) -> Result<IntegerValue, LiteralError> {
+    use IntegerType::*;
+
+    // Digit separators have no effect on the value.
+    let spelling = spelling.replace('\'', "");
+    let spelling = spelling.as_str();
+    let (radix, digits) = match spelling.as_bytes() {
+        [b'0', b'x', ..] | [b'0', b'X', ..] => (16, &spelling[2..]),
+        [b'0', b'b', ..] | [b'0', b'B', ..] => (2, &spelling[2..]),
+        [b'0', ..] => (8, spelling),
+        _ => (10, spelling),
+    };
+    let suffix_begin = digits
+        .find(|c: char| !c.is_digit(radix))
+        .unwrap_or(digits.len());
+    let (digits, suffix) = digits.split_at(suffix_begin);
+    if digits.is_empty() {
+        return Err(LiteralError::InvalidLiteral);
+    }
+
+    let mut value: u128 = 0;
+    for c in digits.chars() {
+        value = value
+            .checked_mul(radix.into())
+            .and_then(|value| value.checked_add(c.to_digit(radix).unwrap().into()))
+            .ok_or(LiteralError::IntegerTooLarge)?;
+    }
+
+    let (unsigned, rank) = match suffix {
+        "" => (false, 0),
+        "u" | "U" => (true, 0),
+        "l" | "L" => (false, 1),
+        "ul" | "uL" | "Ul" | "UL" | "lu" | "Lu" | "lU" | "LU" => (true, 1),
+        "ll" | "LL" => (false, 2),
+        "ull" | "uLL" | "Ull" | "ULL" | "llu" | "LLu" | "llU" | "LLU" => (true, 2),
+        _ => return Err(LiteralError::InvalidLiteral),
+    };
+    let candidates: &[IntegerType] = match (unsigned, radix) {
+        (false, 10) => &[Int, Long, LongLong][rank..],
+        (false, _) => &[
+            Int,
+            UnsignedInt,
+            Long,
+            UnsignedLong,
+            LongLong,
+            UnsignedLongLong,
+        ][rank * 2..],
+        (true, _) => &[UnsignedInt, UnsignedLong, UnsignedLongLong][rank..],
+    };
+    candidates
+        .iter()
+        .find(|&&ty| value <= model.max_value(ty))
+        .map(|&ty| IntegerValue { value, ty })
+        .ok_or(LiteralError::IntegerTooLarge)
+}
+
+/// The types a floating constant may have (Section 6.4.4.2).
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum FloatType {
+    Float,
+    Double,
+    LongDouble,
+}
+
+impl FloatType {
+    /// Returns the name of the type in C.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            FloatType::Float => "float",
+            FloatType::Double => "double",
+            FloatType::LongDouble => "long double",
+        }
+    }
+}
+
+/// The binary formats floating constants are rounded to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum FloatFormat {
+    /// IEEE 754 single precision
+    Binary32,
+    /// IEEE 754 double precision
+    Binary64,
+    /// The 80-bit x87 extended precision, with an explicit integer bit
+    X87Extended,
+}
+
+impl FloatFormat {
+    /// Returns the number of bits in the significand, including the leading one.
+    pub fn precision(self) -> u32 {
+        match self {
+            FloatFormat::Binary32 => 24,
+            FloatFormat::Binary64 => 53,
+            FloatFormat::X87Extended => 64,
+        }
+    }
+
+    /// Returns the largest exponent of a finite value, which is also the bias.
+    pub fn max_exponent(self) -> i32 {
+        match self {
+            FloatFormat::Binary32 => 127,
+            FloatFormat::Binary64 => 1023,
+            FloatFormat::X87Extended => 16383,
+        }
+    }
+
+    /// Returns the smallest exponent of a normal value.
+    pub fn min_exponent(self) -> i32 {
+        1 - self.max_exponent()
+    }
+}
+
+/// How a floating constant has been rounded to its format.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FloatStatus {
+    /// The value is represented exactly
+    Exact,
+    /// The value has been rounded to the nearest representable one
+    Inexact,
+    /// The value is too large, and has become infinity
+    Overflow,
+    /// The value is too small, and has become zero
+    Underflow,
+}
+
+/// The value of a floating constant, rounded to nearest, ties to even.
+///
+/// Finite values are `significand * 2^exponent`, where the significand is
+/// either normalised to exactly [`precision`](FloatFormat::precision) bits
+/// or denormal with the smallest exponent.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FloatValue {
+    pub ty: FloatType,
+    pub format: FloatFormat,
+    pub significand: u128,
+    pub exponent: i32,
+    pub status: FloatStatus,
+}
+
+impl FloatValue {
+    pub fn is_infinite(&self) -> bool {
+        self.status == FloatStatus::Overflow
+    }
+
+    /// Returns the bits of the value encoded in its format, in the low
+    /// 32, 64 or 80 bits.
+    pub fn to_bits(&self) -> u128 {
+        let p = self.format.precision();
+        let explicit_bit = self.format == FloatFormat::X87Extended;
+        let fraction_bits = if explicit_bit { p } else { p - 1 };
+        let (biased_exponent, significand) = if self.is_infinite() {
+            let integer_bit = if explicit_bit { 1 << (p - 1) } else { 0 };
+            (2 * self.format.max_exponent() + 1, integer_bit)
+        } else if self.significand >> (p - 1) == 0 {
+            (0, self.significand)
+        } else {
+            let biased_exponent = self.exponent + (p as i32 - 1) + self.format.max_exponent();
+            let fraction = match explicit_bit {
+                true => self.significand,
+                false => self.significand & !(1 << (p - 1)),
+            };
+            (biased_exponent, fraction)
+        };
+        (biased_exponent as u128) << fraction_bits | significand
+    }
+
+    /// Returns the value as an `f32` if it has been rounded to [`FloatFormat::Binary32`].
+    pub fn to_f32(&self) -> Option<f32> {
+        match self.format {
+            FloatFormat::Binary32 => Some(f32::from_bits(self.to_bits() as u32)),
+            _ => None,
+        }
+    }
+
+    /// Returns the value as an `f64` if it has been rounded to
+    /// [`FloatFormat::Binary32`] or [`FloatFormat::Binary64`].
+    pub fn to_f64(&self) -> Option<f64> {
+        match self.format {
+            FloatFormat::Binary32 => self.to_f32().map(f64::from),
+            FloatFormat::Binary64 => Some(f64::from_bits(self.to_bits() as u64)),
+            _ => None,
+        }
+    }
+}
+
+/// Evaluates the spelling of a decimal or hexadecimal floating constant,
+/// as produced by the lexer.
+///
+/// The suffix decides the type, and `model` the format of `long double`.
+/// The conversion is exact whatever the number of digits.
+pub fn float_value(spelling: &str, model: DataModel) -> Result<FloatValue, LiteralError> {
+    let spelling = spelling.replace('\'', "");
+    let spelling = spelling.as_str();
+    let (radix, rest) = match spelling.as_bytes() {
+        [b'0', b'x', ..] | [b'0', b'X', ..] => (16, &spelling[2..]),
+        _ => (10, spelling),
+    };
+
+    let mut chars = rest.chars().peekable();
+    let mut mantissa = BigUint::zero();
+    let mut significant_digits: i64 = 0;
+    let mut scale: i64 = 0;
+    let mut has_digits = false;
+    let mut has_point = false;
+    while let Some(&c) = chars.peek() {
+        if let Some(digit) = c.to_digit(radix) {
+            mantissa.mul_add(radix, digit);
+            if !mantissa.is_zero() {
+                significant_digits += 1;
+            }
+            if has_point {
+                scale -= 1;
+            }
+            has_digits = true;
+        } else if c == '.' && !has_point {
+            has_point = true;
+        } else {
+            break;
+        }
+        chars.next();
+    }
+
+    let mut exponent: i64 = 0;
+    let has_exponent = match chars.peek() {
+        Some('e') | Some('E') if radix == 10 => true,
+        Some('p') | Some('P') if radix == 16 => true,
+        _ => false,
+    };
+    if has_exponent {
+        chars.next();
+        let negative = chars.peek() == Some(&'-');
+        if let Some('+') | Some('-') = chars.peek() {
+            chars.next();
+        }
+        let mut has_exponent_digits = false;
+        while let Some(digit) = chars.peek().and_then(|c| c.to_digit(10)) {
+            // Saturating is enough, since such exponents overflow anyway.
+            exponent = (exponent * 10 + i64::from(digit)).min(i64::from(u32::MAX));
+            has_exponent_digits = true;
+            chars.next();
+        }
+        if !has_exponent_digits {
+            return Err(LiteralError::InvalidLiteral);
+        }
+        if negative {
+            exponent = -exponent;
+        }
+    }
+    if !has_digits || (radix == 16 && !has_exponent) || (radix == 10 && !has_point && !has_exponent)
+    {
+        return Err(LiteralError::InvalidLiteral);
+    }
+
+    let ty = match chars.collect::<String>().as_str() {
+        "" => FloatType::Double,
+        "f" | "F" => FloatType::Float,
+        "l" | "L" => FloatType::LongDouble,
+        _ => return Err(LiteralError::InvalidLiteral),
+    };
+    let format = match ty {
+        FloatType::Float => FloatFormat::Binary32,
+        FloatType::Double => FloatFormat::Binary64,
+        FloatType::LongDouble => model.long_double_format(),
+    };
+
+    let (significand, exponent, status) = match radix {
+        16 => round_binary(mantissa, exponent + 4 * scale, format),
+        _ => round_decimal(mantissa, significant_digits, exponent + scale, format),
+    };
+    Ok(FloatValue {
+        ty,
+        format,
+        significand,
+        exponent,
+        status,
+    })
+}
+
+/// Rounds `mantissa * 10^exponent`, where the mantissa has `digits`
+/// significant decimal digits.
+fn round_decimal(
+    mantissa: BigUint,
+    digits: i64,
+    exponent: i64,
+    format: FloatFormat,
+) -> (u128, i32, FloatStatus) {
+    // 10^(max+1) and 10^(min-1) are beyond the range of every format, and
+    // are bounds on the magnitude good enough to avoid huge powers of ten.
+    if mantissa.is_zero() {
+        return (0, 0, FloatStatus::Exact);
+    } else if digits + exponent > 5000 {
+        return (0, 0, FloatStatus::Overflow);
+    } else if digits + exponent < -5000 {
+        return (0, 0, FloatStatus::Underflow);
+    }
+    let mut num = mantissa;
+    let mut den = BigUint::one();
+    match exponent >= 0 {
+        true => num.mul_pow10(exponent as u64),
+        false => den.mul_pow10(-exponent as u64),
+    }
+    round(num, den, format)
+}
+
+/// Rounds `mantissa * 2^exponent`.
+fn round_binary(mantissa: BigUint, exponent: i64, format: FloatFormat) -> (u128, i32, FloatStatus) {
+    if mantissa.is_zero() {
+        return (0, 0, FloatStatus::Exact);
+    } else if mantissa.bit_len() as i64 + exponent > 20000 {
+        return (0, 0, FloatStatus::Overflow);
+    } else if mantissa.bit_len() as i64 + exponent < -20000 {
+        return (0, 0, FloatStatus::Underflow);
+    }
+    let mut num = mantissa;
+    let mut den = BigUint::one();
+    match exponent >= 0 {
+        true => num.shl(exponent as u64),
+        false => den.shl(-exponent as u64),
+    }
+    round(num, den, format)
+}
+
+/// Rounds the positive fraction `num / den` to `format`.
+fn round(num: BigUint, den: BigUint, format: FloatFormat) -> (u128, i32, FloatStatus) {
+    let p = format.precision();
+    let min_exponent = format.min_exponent() - (p as i32 - 1);
+
+    // The quotient of the scaled fraction has p or p + 1 bits.
+    let mut exponent = (num.bit_len() as i64 - den.bit_len() as i64 - p as i64)
+        .max(i64::from(min_exponent)) as i32;
+    let (mut significand, mut remainder, den) = loop {
+        let mut num = num.clone();
+        let mut den = den.clone();
+        match exponent >= 0 {
+            true => den.shl(exponent as u64),
+            false => num.shl(-exponent as u64),
+        }
+        let (quotient, remainder) = num.div_rem(&den, p + 1);
+        if quotient >> p == 0 {
+            break (quotient, remainder, den);
+        }
+        exponent += 1;
+    };
+
+    let status = match remainder.is_zero() {
+        true => FloatStatus::Exact,
+        false => FloatStatus::Inexact,
+    };
+    remainder.shl(1);
+    match remainder.cmp(&den) {
+        Ordering::Greater => significand += 1,
+        Ordering::Equal if significand & 1 == 1 => significand += 1,
+        _ => (),
+    }
+    if significand >> p != 0 {
+        significand >>= 1;
+        exponent += 1;
+    }
+
+    if significand == 0 {
+        (0, 0, FloatStatus::Underflow)
+    } else if exponent + (p as i32 - 1) > format.max_exponent() {
+        (0, 0, FloatStatus::Overflow)
+    } else {
+        (significand, exponent, status)
+    }
+}
+
+/// An arbitrary-precision unsigned integer, just enough for exact rounding.
+///
+/// The limbs are little-endian, without leading zeros.
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct BigUint(Vec<u32>);
+
+impl BigUint {
+    fn zero() -> Self {
+        BigUint(Vec::new())
+    }
+
+    fn one() -> Self {
+        BigUint(vec![1])
+    }
+
+    fn is_zero(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    fn bit_len(&self) -> u64 {
+        match self.0.last() {
+            Some(last) => self.0.len() as u64 * 32 - u64::from(last.leading_zeros()),
+            None => 0,
+        }
+    }
+
+    /// Computes `self * m + a`.
+    fn mul_add(&mut self, m: u32, a: u32) {
+        let mut carry = u64::from(a);
+        for limb in self.0.iter_mut() {
+            let product = u64::from(*limb) * u64::from(m) + carry;
+            *limb = product as u32;
+            carry = product >> 32;
+        }
+        if carry > 0 {
+            self.0.push(carry as u32);
+        }
+    }
+
+    fn mul_pow10(&mut self, mut n: u64) {
+        while n >= 9 {
+            self.mul_add(1_000_000_000, 0);
+            n -= 9;
+        }
+        self.mul_add(10u32.pow(n as u32), 0);
+    }
+
+    fn shl(&mut self, n: u64) {
+        if self.is_zero() {
+            return;
+        }
+        let bits = (n % 32) as u32;
+        if bits > 0 {
+            let mut carry = 0;
+            for limb in self.0.iter_mut() {
+                let shifted = *limb >> (32 - bits);
+                *limb = *limb << bits | carry;
+                carry = shifted;
+            }
+            if carry > 0 {
+                self.0.push(carry);
+            }
+        }
+        let limbs = (n / 32) as usize;
+        self.0.splice(0..0, std::iter::repeat_n(0, limbs));
+    }
+
+    /// Computes `self - other`, given that `self >= other`.
+    fn sub(&mut self, other: &BigUint) {
+        let mut borrow = false;
+        for (i, limb) in self.0.iter_mut().enumerate() {
+            let (diff, overflow1) = limb.overflowing_sub(other.0.get(i).copied().unwrap_or(0));
+            let (diff, overflow2) = diff.overflowing_sub(borrow as u32);
+            *limb = diff;
+            borrow = overflow1 || overflow2;
+        }
+        while self.0.last() == Some(&0) {
+            self.0.pop();
+        }
+    }
+
+    /// Returns the quotient and the remainder of `self / den`, given that
+    /// the quotient fits in `bits` bits.
+    fn div_rem(mut self, den: &BigUint, bits: u32) -> (u128, BigUint) {
+        let mut quotient = 0;
+        for i in (0..bits).rev() {
+            let mut shifted = den.clone();
+            shifted.shl(i.into());
+            if self >= shifted {
+                self.sub(&shifted);
+                quotient |= 1 << i;
+            }
+        }
+        (quotient, self)
+    }
+}
+
+impl PartialOrd for BigUint {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for BigUint {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0
+            .len()
+            .cmp(&other.0.len())
+            .then_with(|| self.0.iter().rev().cmp(other.0.iter().rev()))
+    }
+}
+
+/// The contents of a character constant or string literal, in code units
+/// of the execution character set: UTF-8 for plain and `u8` ones, UTF-16
+/// for `u` ones, UTF-32 for `U` ones, and UTF-16 or UTF-32 for wide ones
+/// depending on the width of `wchar_t`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LiteralUnits {
+    pub encoding: Encoding,
+    /// The width of each code unit in bits
+    pub unit_width: u32,
+    pub units: Vec<u32>,
+    /// Byte ranges in the spelling of unknown escape sequences, which are
+    /// kept as the char after the backslash
+    pub unknown_escapes: Vec<Range<usize>>,
+}
+
+/// Decodes the spelling of a character constant or string literal, as
+/// produced by the lexer, replacing the escape sequences of Section 6.4.4.4
+/// in `dialect`, where `\u` and `\U` are unknown before C99.
+///
+/// No terminating null character is appended to string literals.
+pub fn decode_literal(
+    spelling: &str,
+    dialect: Dialect,
+    model: DataModel,
+) -> Result<LiteralUnits, LiteralError> {
+    let (encoding, _) = split_encoding_prefix(spelling)?;
+    decode_literal_as(spelling, encoding, dialect, model)
+}
+
+/// Returns the encoding of a character constant or string literal, and
+/// the byte offset of its opening quote.
+fn split_encoding_prefix(spelling: &str) -> Result<(Encoding, usize), LiteralError> {
+    let quote_begin = spelling.find(['\'', '"']).unwrap_or(0);
+    let encoding = match &spelling[..quote_begin] {
+        "" => Encoding::Plain,
+        "u8" => Encoding::Utf8,
+        "L" => Encoding::Wide,
+        "u" => Encoding::Utf16,
+        "U" => Encoding::Utf32,
+        _ => return Err(LiteralError::InvalidLiteral),
+    };
+    Ok((encoding, quote_begin))
+}
+
+/// Decodes a character constant or string literal as if it had the prefix
+/// of `encoding`, which happens to string literals concatenated with
+/// prefixed ones (Section 6.4.5).
+pub(crate) fn decode_literal_as(
+    spelling: &str,
+    encoding: Encoding,
+    dialect: Dialect,
+    model: DataModel,
+) -> Result<LiteralUnits, LiteralError> {
+    let (_, quote_begin) = split_encoding_prefix(spelling)?;
+    let unit_width = match encoding {
+        Encoding::Plain | Encoding::Utf8 => 8,
+        Encoding::Wide => model.wchar_width(),
+        Encoding::Utf16 => 16,
+        Encoding::Utf32 => 32,
+    };
+    let quoted = &spelling[quote_begin..];
+    let quote = match quoted.chars().next() {
+        Some(quote @ '\'') | Some(quote @ '"') => quote,
+        _ => return Err(LiteralError::InvalidLiteral),
+    };
+    if quoted.len() < 2 || !quoted.ends_with(quote) {
+        return Err(LiteralError::InvalidLiteral);
+    }
+    let body_begin = spelling.len() - quoted.len() + 1;
+    let body = &quoted[1..quoted.len() - 1];
+    let max_value = (1u64 << unit_width) - 1;
+
+    let mut literal = LiteralUnits {
+        encoding,
+        unit_width,
+        units: Vec::new(),
+        unknown_escapes: Vec::new(),
+    };
+    let mut chars = body.char_indices().peekable();
+    while let Some((escape_begin, c)) = chars.next() {
+        if c != '\\' {
+            literal.push_char(c);
+            continue;
+        }
+        let (_, c) = chars.next().ok_or(LiteralError::InvalidLiteral)?;
+        let value = match c {
+            '\'' | '"' | '?' | '\\' => c as u32,
+            'a' => 0x07,
+            'b' => 0x08,
+            'f' => 0x0c,
+            'n' => 0x0a,
+            'r' => 0x0d,
+            't' => 0x09,
+            'v' => 0x0b,
+            '0'..='7' => {
+                let mut value = c.to_digit(8).unwrap();
+                for _ in 0..2 {
+                    match chars.peek().and_then(|&(_, c)| c.to_digit(8)) {
+                        Some(digit) => value = value * 8 + digit,
+                        None => break,
+                    }
+                    chars.next();
+                }
+                if u64::from(value) > max_value {
+                    return Err(LiteralError::OctalEscapeOutOfRange);
+                }
+                value
+            }
+            'x' => {
+                let mut value: u64 = 0;
+                let mut has_digits = false;
+                while let Some(digit) = chars.peek().and_then(|&(_, c)| c.to_digit(16)) {
+                    value = (value * 16 + u64::from(digit)).min(max_value + 1);
+                    has_digits = true;
+                    chars.next();
+                }
+                if !has_digits {
+                    return Err(LiteralError::EmptyHexEscape);
+                }
+                if value > max_value {
+                    return Err(LiteralError::HexEscapeOutOfRange);
+                }
+                value as u32
+            }
+            'u' | 'U' if dialect.has_ucn() => {
+                let len = if c == 'u' { 4 } else { 8 };
+                let mut value = 0;
+                for _ in 0..len {
+                    match chars.next().and_then(|(_, c)| c.to_digit(16)) {
+                        Some(digit) => value = value * 16 + digit,
+                        None => return Err(LiteralError::InvalidUniversalCharacterName),
+                    }
+                }
+                if !unicode::is_valid_ucn(value) {
+                    return Err(LiteralError::InvalidUniversalCharacterName);
+                }
+                literal.push_char(std::char::from_u32(value).unwrap());
+                continue;
+            }
+            _ => {
+                let escape_end = escape_begin + 1 + c.len_utf8();
+                literal
+                    .unknown_escapes
+                    .push(body_begin + escape_begin..body_begin + escape_end);
+                literal.push_char(c);
+                continue;
+            }
+        };
+        literal.units.push(value);
+    }
+    // Section 6.4.4.4 of C23: a UTF-8 character constant is a single code unit.
+    if quote == '\'' && encoding == Encoding::Utf8 && literal.units.len() > 1 {
+        return Err(LiteralError::Utf8CharacterTooLarge);
+    }
+    Ok(literal)
+}
+
+impl LiteralUnits {
+    /// Appends the code units encoding `c`.
+    fn push_char(&mut self, c: char) {
+        match self.unit_width {
+            8 => {
+                let mut buf = [0; 4];
+                let bytes = c.encode_utf8(&mut buf).bytes();
+                self.units.extend(bytes.map(u32::from));
+            }
+            16 => {
+                let mut buf = [0; 2];
+                let units = c.encode_utf16(&mut buf).iter();
+                self.units.extend(units.map(|&unit| u32::from(unit)));
+            }
+            _ => self.units.push(c as u32),
+        }
+    }
+}
+
+impl Token<'_> {
+    /// Evaluates an integer constant token; see [`integer_value`].
+    pub fn integer_value(&self, model: DataModel) -> Result<IntegerValue, LiteralError> {
+        match self.kind {
+            Const(Integer) => integer_value(&self.spelling(), model),
+            _ => Err(LiteralError::InvalidLiteral),
+        }
+    }
+
+    /// Decodes a character constant or string literal token; see [`decode_literal`].
+    pub fn decode_literal(
+        &self,
+        dialect: Dialect,
+        model: DataModel,
+    ) -> Result<LiteralUnits, LiteralError> {
+        match self.kind {
+            Const(Char(_)) | StrLit(_) => decode_literal(&self.spelling(), dialect, model),
+            _ => Err(LiteralError::InvalidLiteral),
+        }
+    }
+
+    /// Evaluates a floating constant token; see [`float_value`].
+    pub fn float_value(&self, model: DataModel) -> Result<FloatValue, LiteralError> {
+        match self.kind {
+            Const(Float) => float_value(&self.spelling(), model),
+            _ => Err(LiteralError::InvalidLiteral),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::Lexer;
+    use IntegerType::*;
+
+    fn eval(src: &str, model: DataModel) -> Result<(u128, IntegerType), LiteralError> {
+        let token = Lexer::new(src).iter().next().unwrap();
+        token
+            .integer_value(model)
+            .map(|IntegerValue { value, ty }| (value, ty))
+    }
+
+    #[test]
+    fn test_integer_values() {
+        let lp64 = DataModel::LP64;
+        assert_eq!(eval("0", lp64), Ok((0, Int)));
+        assert_eq!(eval("0777", lp64), Ok((0o777, Int)));
+        assert_eq!(eval("0xDEADbeef", lp64), Ok((0xdead_beef, UnsignedInt)));
+        assert_eq!(eval("2147483648", lp64), Ok((2147483648, Long)));
+        assert_eq!(eval("42u", lp64), Ok((42, UnsignedInt)));
+        assert_eq!(eval("42LU", lp64), Ok((42, UnsignedLong)));
+        assert_eq!(eval("42ll", lp64), Ok((42, LongLong)));
+        assert_eq!(eval("0x1ll", lp64), Ok((1, LongLong)));
+        assert_eq!(
+            eval("18446744073709551615", lp64),
+            Err(LiteralError::IntegerTooLarge)
+        );
+        assert_eq!(
+            eval("0xffffffffffffffff", lp64),
+            Ok((u64::MAX.into(), UnsignedLong))
+        );
+        assert_eq!(
+            eval("340282366920938463463374607431768211456", lp64),
+            Err(LiteralError::IntegerTooLarge)
+        );
+        assert_eq!(eval("1.0", lp64), Err(LiteralError::InvalidLiteral));
+    }
+
+    #[test]
+    fn test_c23_integer_values() {
+        let eval = |src| {
+            let token = Lexer::new(src)
+                .with_dialect(Dialect::C23)
+                .iter()
+                .next()
+                .unwrap();
+            token
+                .integer_value(DataModel::LP64)
+                .map(|value| (value.value, value.ty))
+        };
+        assert_eq!(eval("0b1010"), Ok((10, Int)));
+        assert_eq!(
+            eval("0B11111111111111111111111111111111"),
+            Ok((u32::MAX.into(), UnsignedInt))
+        );
+        assert_eq!(eval("1'000'000"), Ok((1_000_000, Int)));
+        assert_eq!(
+            eval("0x7FFF'FFFF'FFFF'FFFFull"),
+            Ok((i64::MAX as u128, UnsignedLongLong))
+        );
+        assert_eq!(eval("0'17"), Ok((0o17, Int)));
+    }
+
+    #[test]
+    fn test_data_models() {
+        assert_eq!(
+            eval("2147483648", DataModel::ILP32),
+            Ok((2147483648, LongLong))
+        );
+        assert_eq!(
+            eval("2147483648", DataModel::LLP64),
+            Ok((2147483648, LongLong))
+        );
+        assert_eq!(
+            eval("0x80000000", DataModel::ILP32),
+            Ok((0x8000_0000, UnsignedInt))
+        );
+        assert_eq!(
+            eval("0x100000000", DataModel::LLP64),
+            Ok((1 << 32, LongLong))
+        );
+        assert_eq!(
+            eval("0x8000000000000000l", DataModel::ILP32),
+            Ok((1 << 63, UnsignedLongLong))
+        );
+    }
+
+    fn eval_float(src: &str, model: DataModel) -> FloatValue {
+        let token = Lexer::new(src).iter().next().unwrap();
+        token.float_value(model).unwrap()
+    }
+
+    #[test]
+    fn test_decimal_floats() {
+        let cases = [
+            "0.0",
+            "1.0",
+            "0.1",
+            ".5",
+            "3.",
+            "1e10",
+            "1E+10",
+            "2.5e-3",
+            "123456789012345678901234567890.0",
+            "0.30000000000000004",
+            "2.2250738585072011e-308",
+            "2.2250738585072014e-308",
+            "4.9406564584124654e-324",
+            "2.4703282292062328e-324",
+            "1.7976931348623157e308",
+            "9007199254740993.0",
+            "0.000000000000000000000000000000000000000000001",
+        ];
+        for case in cases.iter() {
+            let double = eval_float(case, DataModel::LP64);
+            assert_eq!(double.ty, FloatType::Double);
+            assert_eq!(
+                double.to_f64(),
+                Some(case.parse::<f64>().unwrap()),
+                "{}",
+                case
+            );
+            let float = eval_float(&format!("{}f", case), DataModel::LP64);
+            assert_eq!(float.ty, FloatType::Float);
+            assert_eq!(
+                float.to_f32(),
+                Some(case.parse::<f32>().unwrap()),
+                "{}",
+                case
+            );
+        }
+        assert_eq!(
+            eval_float("0.5", DataModel::LP64).status,
+            FloatStatus::Exact
+        );
+        assert_eq!(
+            eval_float("0.1", DataModel::LP64).status,
+            FloatStatus::Inexact
+        );
+    }
+
+    #[test]
+    fn test_digit_separators_in_floats() {
+        let token = Lexer::new("1'0.2'5e1'0")
+            .with_dialect(Dialect::C23)
+            .iter()
+            .next()
+            .unwrap();
+        let value = token.float_value(DataModel::LP64).unwrap();
+        assert_eq!(value.to_f64(), Some(10.25e10));
+    }
+
+    #[test]
+    fn test_hexadecimal_floats() {
+        let value = |src| eval_float(src, DataModel::LP64).to_f64().unwrap();
+        assert_eq!(value("0x1.8p-3"), 0.1875);
+        assert_eq!(value("0x.8p1"), 1.0);
+        assert_eq!(value("0XA.Bp2f"), 42.75);
+        assert_eq!(value("0x1p-1074"), f64::from_bits(1));
+        assert_eq!(value("0x1.fffffffffffffp1023"), f64::MAX);
+        assert_eq!(value("0x1.00000000000008p0"), 1.0);
+        assert_eq!(value("0x1.00000000000018p0"), 1.0 + 2.0 * f64::EPSILON);
+    }
+
+    #[test]
+    fn test_long_double_floats() {
+        let value = eval_float("1.0L", DataModel::LP64);
+        assert_eq!(value.ty, FloatType::LongDouble);
+        assert_eq!(value.format, FloatFormat::X87Extended);
+        assert_eq!(value.to_bits(), 0x3fff_8000_0000_0000_0000);
+        assert_eq!(value.to_f64(), None);
+        let value = eval_float("0.1l", DataModel::LP64);
+        assert_eq!(value.to_bits(), 0x3ffb_cccc_cccc_cccc_cccd);
+        assert_eq!(
+            eval_float("1e400L", DataModel::LP64).status,
+            FloatStatus::Inexact
+        );
+        let value = eval_float("0.1L", DataModel::LLP64);
+        assert_eq!(value.format, FloatFormat::Binary64);
+        assert_eq!(value.to_f64(), Some(0.1));
+    }
+
+    #[test]
+    fn test_float_overflow_and_underflow() {
+        let status = |src| eval_float(src, DataModel::LP64).status;
+        assert_eq!(status("1e309"), FloatStatus::Overflow);
+        assert_eq!(status("1e39f"), FloatStatus::Overflow);
+        assert_eq!(status("1e999999999999"), FloatStatus::Overflow);
+        assert_eq!(status("0x1p1024"), FloatStatus::Overflow);
+        assert_eq!(status("1e-400"), FloatStatus::Underflow);
+        assert_eq!(status("1e-46f"), FloatStatus::Underflow);
+        assert_eq!(status("0x1p-1076"), FloatStatus::Underflow);
+        assert_eq!(status("0e999999"), FloatStatus::Exact);
+        assert!(eval_float("1e309", DataModel::LP64).is_infinite());
+        assert_eq!(
+            eval_float("1e309", DataModel::LP64).to_f64(),
+            Some(f64::INFINITY)
+        );
+        assert_eq!(
+            eval_float("1e5000L", DataModel::LP64).to_bits(),
+            0x7fff_8000_0000_0000_0000
+        );
+    }
+
+    fn decode(src: &str, model: DataModel) -> Result<Vec<u32>, LiteralError> {
+        let token = Lexer::new(src).iter().next().unwrap();
+        token
+            .decode_literal(Dialect::C99, model)
+            .map(|literal| literal.units)
+    }
+
+    #[test]
+    fn test_escape_sequences() {
+        let lp64 = DataModel::LP64;
+        assert_eq!(decode(r#""a\n\t\0""#, lp64), Ok(vec![0x61, 0x0a, 0x09, 0]));
+        assert_eq!(decode(r#"'\'' "\"\?\\""#, lp64), Ok(vec![0x27]));
+        assert_eq!(decode(r#""\a\b\f\r\v""#, lp64), Ok(vec![7, 8, 12, 13, 11]));
+        assert_eq!(decode(r#""\1234\377""#, lp64), Ok(vec![0o123, 0x34, 0xff]));
+        assert_eq!(decode(r#""\x41g\xFF""#, lp64), Ok(vec![0x41, 0x67, 0xff]));
+        assert_eq!(decode(r#""\x000041""#, lp64), Ok(vec![0x41]));
+        assert_eq!(decode(r#""éé""#, lp64), Ok(vec![0xc3, 0xa9, 0xc3, 0xa9]));
+        assert_eq!(decode(r#"L"é\U0001F600""#, lp64), Ok(vec![0xe9, 0x1f600]));
+        assert_eq!(
+            decode(r#"L"\U0001F600""#, DataModel::LLP64),
+            Ok(vec![0xd83d, 0xde00])
+        );
+        assert_eq!(decode(r#"L'\xFFFFFFFF'"#, lp64), Ok(vec![0xffff_ffff]));
+        assert_eq!(decode(r#"L'\777'"#, lp64), Ok(vec![0o777]));
+
+        let c11 = |src| {
+            let token = Lexer::new(src)
+                .with_dialect(Dialect::C11)
+                .iter()
+                .next()
+                .unwrap();
+            token
+                .decode_literal(Dialect::C11, lp64)
+                .map(|literal| (literal.encoding, literal.units))
+        };
+        assert_eq!(c11(r#"u8"\u00e9""#), Ok((Encoding::Utf8, vec![0xc3, 0xa9])));
+        assert_eq!(
+            c11(r#"u"\U0001F600""#),
+            Ok((Encoding::Utf16, vec![0xd83d, 0xde00]))
+        );
+        assert_eq!(
+            c11(r#"U"\U0001F600""#),
+            Ok((Encoding::Utf32, vec![0x1f600]))
+        );
+        assert_eq!(
+            decode_literal(r#"u'\x10000'"#, Dialect::C11, lp64).map(|literal| literal.units),
+            Err(LiteralError::HexEscapeOutOfRange)
+        );
+
+        let literal = Lexer::new(r#""\q\e""#).iter().next().unwrap();
+        let literal = literal.decode_literal(Dialect::C99, lp64).unwrap();
+        assert_eq!(literal.units, vec![0x71, 0x65]);
+        assert_eq!(literal.unknown_escapes, vec![1..3, 3..5]);
+    }
+
+    #[test]
+    fn test_invalid_escape_sequences() {
+        let lp64 = DataModel::LP64;
+        let literal =
+            |src: &str| decode_literal(src, Dialect::C99, lp64).map(|literal| literal.units);
+        assert_eq!(literal(r#""\x""#), Err(LiteralError::EmptyHexEscape));
+        assert_eq!(
+            literal(r#""\x100""#),
+            Err(LiteralError::HexEscapeOutOfRange)
+        );
+        assert_eq!(
+            literal(r#"L"\x100000000""#),
+            Err(LiteralError::HexEscapeOutOfRange)
+        );
+        assert_eq!(
+            literal(r#""\400""#),
+            Err(LiteralError::OctalEscapeOutOfRange)
+        );
+        assert_eq!(
+            literal(r#""\u12""#),
+            Err(LiteralError::InvalidUniversalCharacterName)
+        );
+        assert_eq!(
+            literal(r#""\uD800""#),
+            Err(LiteralError::InvalidUniversalCharacterName)
+        );
+        assert_eq!(literal("1"), Err(LiteralError::InvalidLiteral));
+        assert_eq!(literal("u8'ab'"), Err(LiteralError::Utf8CharacterTooLarge));
+        assert_eq!(literal("u8'é'"), Err(LiteralError::Utf8CharacterTooLarge));
+        assert_eq!(literal("u8'\\xff'"), Ok(vec![0xff]));
+    }
+}
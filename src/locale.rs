@@ -0,0 +1,172 @@
+//! Localized messages.
+//!
+//! Every message shown to users, from diagnostics to the statistics, is
+//! looked up by key in the [`Catalog`] of the current thread, which is the
+//! built-in English one unless [`set_catalog`] says otherwise.
+
+use anyhow::{anyhow, Result};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::rc::Rc;
+use std::str::FromStr;
+
+/// A language with a built-in catalog.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Language {
+    #[default]
+    English,
+    Chinese,
+}
+
+impl Language {
+    /// Returns the language of the messages asked for by the environment,
+    /// i.e. the first of `LC_ALL`, `LC_MESSAGES` and `LANG` that is set.
+    pub fn from_env() -> Option<Self> {
+        ["LC_ALL", "LC_MESSAGES", "LANG"]
+            .iter()
+            .filter_map(|name| std::env::var(name).ok())
+            .find(|value| !value.is_empty())
+            .and_then(|value| value.parse().ok())
+    }
+}
+
+impl FromStr for Language {
+    type Err = String;
+
+    /// Parses a language code such as `zh`, or a locale such as `zh_CN.UTF-8`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let code = s.split(['_', '-', '.', '@']).next().unwrap_or_default();
+        match code {
+            "en" | "C" | "POSIX" => Ok(Language::English),
+            "zh" => Ok(Language::Chinese),
+            _ => Err(format!("unsupported language `{}`", s)),
+        }
+    }
+}
+
+/// Messages by key.
+///
+/// Catalogs are text files of `key = message` lines, where `{name}` in a
+/// message stands for an argument and lines starting with `#` are comments;
+/// see `src/locales/en.txt` for every key.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Catalog {
+    messages: HashMap<String, String>,
+}
+
+impl Catalog {
+    /// Returns the built-in catalog of `language`, falling back to English
+    /// for the messages it lacks.
+    pub fn builtin(language: Language) -> Self {
+        let mut catalog = Catalog::parse(include_str!("locales/en.txt")).unwrap();
+        if let Language::Chinese = language {
+            catalog.extend(Catalog::parse(include_str!("locales/zh.txt")).unwrap());
+        }
+        catalog
+    }
+
+    /// Parses the text of a catalog.
+    pub fn parse(text: &str) -> Result<Self> {
+        let mut messages = HashMap::new();
+        for (i, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (key, message) = line
+                .split_once('=')
+                .ok_or_else(|| anyhow!("line {}: expected `key = message`", i + 1))?;
+            messages.insert(key.trim().to_string(), message.trim().to_string());
+        }
+        Ok(Catalog { messages })
+    }
+
+    /// Reads the catalog at `path`.
+    pub fn open(path: &Path) -> Result<Self> {
+        let text = fs::read_to_string(path)?;
+        Catalog::parse(&text).map_err(|e| anyhow!("{}: {}", path.display(), e))
+    }
+
+    /// Adds the messages of `other`, replacing those with the same keys.
+    pub fn extend(&mut self, other: Catalog) {
+        self.messages.extend(other.messages);
+    }
+
+    /// Returns the message of `key`, if any.
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.messages.get(key).map(String::as_str)
+    }
+
+    /// Returns the message of `key` with `args` filled in, or `key` itself
+    /// if there is no such message.
+    pub fn format(&self, key: &str, args: &[(&str, &dyn fmt::Display)]) -> String {
+        let mut message = self.get(key).unwrap_or(key).to_string();
+        for (name, value) in args {
+            message = message.replace(&format!("{{{}}}", name), &value.to_string());
+        }
+        message
+    }
+
+    /// Returns the keys of the catalog, in no particular order.
+    pub fn keys(&self) -> impl Iterator<Item = &str> {
+        self.messages.keys().map(String::as_str)
+    }
+}
+
+thread_local! {
+    static CATALOG: RefCell<Rc<Catalog>> = RefCell::new(Rc::new(Catalog::builtin(Language::English)));
+}
+
+/// Makes `catalog` the one messages are looked up in on the current thread.
+pub fn set_catalog(catalog: Catalog) {
+    CATALOG.with(|current| *current.borrow_mut() = Rc::new(catalog));
+}
+
+/// Returns the message of `key` in the current catalog, with `args` filled in.
+pub fn message(key: &str, args: &[(&str, &dyn fmt::Display)]) -> String {
+    let catalog = CATALOG.with(|current| current.borrow().clone());
+    catalog.format(key, args)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_languages() {
+        assert_eq!("zh_CN.UTF-8".parse(), Ok(Language::Chinese));
+        assert_eq!("en_US".parse(), Ok(Language::English));
+        assert_eq!("C".parse(), Ok(Language::English));
+        assert!("fr_FR".parse::<Language>().is_err());
+    }
+
+    #[test]
+    fn test_catalogs() {
+        let english = Catalog::builtin(Language::English);
+        let chinese = Catalog::parse(include_str!("locales/zh.txt")).unwrap();
+        let mut missing: Vec<_> = english
+            .keys()
+            .filter(|key| chinese.get(key).is_none())
+            .collect();
+        missing.sort_unstable();
+        assert_eq!(missing, [] as [&str; 0]);
+
+        let mut catalog = Catalog::builtin(Language::Chinese);
+        catalog.extend(Catalog::parse("# custom\nE0106 = `{name}` defined twice\n").unwrap());
+        assert_eq!(
+            catalog.format("E0106", &[("name", &"N")]),
+            "`N` defined twice"
+        );
+        assert_eq!(catalog.format("E0002", &[]), "缺少结尾的 '\"' 字符");
+        assert_eq!(catalog.format("no.such.key", &[]), "no.such.key");
+        assert!(Catalog::parse("E0001 unterminated").is_err());
+
+        set_catalog(catalog);
+        assert_eq!(message("stats.errors", &[]), "错误");
+        set_catalog(Catalog::builtin(Language::English));
+        assert_eq!(message("stats.errors", &[]), "Errors");
+    }
+}
@@ -1,25 +1,15 @@
-use anyhow::Result;
-use std::io::Write;
+use anyhow::{bail, Result};
+use std::collections::BTreeSet;
 use std::path::PathBuf;
 use structopt::StructOpt;
-use termcolor::{
-    Color::{Red, White},
-    ColorChoice, ColorSpec, StandardStream, WriteColor,
-};
-
-#[cfg(test)]
-#[macro_use]
-mod test_utils;
-
-mod lexer;
-mod source;
-mod stats;
-mod token;
+use termcolor::StandardStream;
 
-use lexer::Lexer;
-use source::SourceFile;
-use stats::Statistics;
-use token::*;
+use clex::locale::{self, Catalog, Language};
+use clex::token::*;
+use clex::{
+    explain, DataModel, Diagnostic, Dialect, Emitter, HumanEmitter, Label, Lexer, Location,
+    Preprocessor, Severity, SourceFile, Statistics,
+};
 
 #[derive(StructOpt, Debug)]
 #[structopt(
@@ -28,52 +18,250 @@ use token::*;
     about = env!("CARGO_PKG_DESCRIPTION"),
 )]
 struct Opt {
-    #[structopt(parse(from_os_str), help = "The source code file")]
-    source: PathBuf,
+    #[structopt(
+        parse(from_os_str),
+        required_unless = "explain",
+        help = "The source code file"
+    )]
+    source: Option<PathBuf>,
+    #[structopt(
+        long = "std",
+        default_value = "c99",
+        possible_values = &["c89", "c90", "c99", "c11", "c17", "c18", "c23", "c2x"],
+        help = "The C standard to lex against"
+    )]
+    std: Dialect,
+    #[structopt(
+        long = "model",
+        default_value = "lp64",
+        possible_values = &["ilp32", "lp64", "llp64"],
+        help = "The data model of the target"
+    )]
+    model: DataModel,
+    #[structopt(long = "trigraphs", help = "Replace trigraph sequences such as `??=`")]
+    trigraphs: bool,
+    #[structopt(
+        short = "E",
+        long = "preprocess",
+        help = "Execute directives and expand macros before lexing"
+    )]
+    preprocess: bool,
+    #[structopt(
+        short = "I",
+        value_name = "dir",
+        parse(from_os_str),
+        number_of_values = 1,
+        help = "Add a directory to search for #include files"
+    )]
+    include_paths: Vec<PathBuf>,
+    #[structopt(
+        long = "physical",
+        help = "Report physical locations instead of those set by #line"
+    )]
+    physical: bool,
+    #[structopt(
+        long = "explain",
+        value_name = "code",
+        help = "Describe an error such as E0002 in detail, with examples, in English only"
+    )]
+    explain: Option<String>,
+    #[structopt(
+        long = "lang",
+        possible_values = &["en", "zh"],
+        help = "The language of messages, taken from LANG if not given"
+    )]
+    lang: Option<Language>,
+    #[structopt(
+        long = "catalog",
+        value_name = "file",
+        parse(from_os_str),
+        number_of_values = 1,
+        help = "Add a file of translated messages"
+    )]
+    catalogs: Vec<PathBuf>,
+}
+
+/// Prints diagnostics to stderr, counting the warnings and keeping the
+/// codes of the errors that have an explanation.
+struct Reporter {
+    emitter: HumanEmitter<StandardStream>,
+    warnings: usize,
+    explained: BTreeSet<&'static str>,
+}
+
+impl Emitter for Reporter {
+    fn emit(&mut self, source: &SourceFile, diagnostic: &Diagnostic) -> Result<()> {
+        match diagnostic.severity {
+            Severity::Warning => self.warnings += 1,
+            Severity::Error => {
+                let code = diagnostic.code.filter(|code| explain(code).is_some());
+                self.explained.extend(code);
+            }
+            Severity::Note => (),
+        }
+        self.emitter.emit(source, diagnostic)
+    }
 }
 
 fn main() -> Result<()> {
     let opt = Opt::from_args();
+    let language = opt.lang.or_else(Language::from_env).unwrap_or_default();
+    let mut catalog = Catalog::builtin(language);
+    for path in &opt.catalogs {
+        catalog.extend(Catalog::open(path)?);
+    }
+    locale::set_catalog(catalog);
 
-    let source = SourceFile::open(opt.source)?;
+    if let Some(code) = &opt.explain {
+        match explain(code) {
+            Some(explanation) => print!("{}", explanation),
+            None => bail!(locale::message("no-explanation", &[("code", code)])),
+        }
+        return Ok(());
+    }
+
+    let source = SourceFile::open(opt.source.clone().unwrap())?;
     let mut stats = Statistics::new(&source);
-    let lexer = Lexer::new(source.src.as_str());
-    for token in lexer.iter() {
-        let (line, column) = source.lookup_line_column(token.char_range.start);
-        if let Error(error_kind) = token.kind {
-            let mut stderr = StandardStream::stderr(ColorChoice::Auto);
-            stderr.set_color(ColorSpec::new().set_fg(Some(White)).set_bold(true))?;
-            write!(
-                &mut stderr,
-                "{}:{}:{}: ",
-                source.path.to_str().unwrap(),
-                line + 1,
-                column + 1,
-            )?;
-            stderr.set_color(ColorSpec::new().set_fg(Some(Red)).set_bold(true))?;
-            write!(&mut stderr, "error: ")?;
-            stderr.set_color(ColorSpec::new().set_fg(Some(White)).set_bold(true))?;
-            writeln!(&mut stderr, "{:?}", error_kind)?;
-            stderr.reset()?;
-            source.display_error_hint(&token)?;
-        } else {
+    let mut reporter = Reporter {
+        emitter: HumanEmitter::stderr().with_physical(opt.physical),
+        warnings: 0,
+        explained: BTreeSet::new(),
+    };
+    match opt.preprocess {
+        true => preprocess(&opt, &source, &mut stats, &mut reporter)?,
+        false => lex(&opt, &source, &mut stats, &mut reporter)?,
+    }
+
+    let warnings = reporter.warnings;
+    if warnings > 0 {
+        let key = match warnings {
+            1 => "warning-generated",
+            _ => "warnings-generated",
+        };
+        println!("{}", locale::message(key, &[("count", &warnings)]));
+    }
+    let key = match stats.errors {
+        0 | 1 => "error-generated",
+        _ => "errors-generated",
+    };
+    println!("{}", locale::message(key, &[("count", &stats.errors)]));
+    let command = env!("CARGO_PKG_NAME");
+    let explained: Vec<_> = reporter.explained.into_iter().collect();
+    match explained.as_slice() {
+        [] => (),
+        [code] => println!(
+            "{}",
+            locale::message("explained-one", &[("command", &command), ("code", code)])
+        ),
+        codes => {
+            let list = codes.join(", ");
+            println!("{}", locale::message("explained-many", &[("codes", &list)]));
             println!(
-                "{}:{}:{}: {}",
-                source.path.to_str().unwrap(),
-                line + 1,
-                column + 1,
-                token,
+                "{}",
+                locale::message(
+                    "explained-try",
+                    &[("command", &command), ("code", &codes[0])]
+                )
             );
         }
+    }
+
+    println!("\n{} \n{}", locale::message("stats", &[]), stats);
+    Ok(())
+}
+
+/// Lexes `source` as it is, following `#line` directives for locations.
+fn lex(
+    opt: &Opt,
+    source: &SourceFile,
+    stats: &mut Statistics,
+    emitter: &mut impl Emitter,
+) -> Result<()> {
+    let mut lexer = Lexer::new(source.src.as_str())
+        .with_dialect(opt.std)
+        .with_data_model(opt.model)
+        .with_trigraphs(opt.trigraphs);
+    while let Some(token) = lexer.advance_token() {
+        for marker in lexer.take_line_markers() {
+            source.add_line_marker(marker);
+        }
+        if token.kind.is_trivia() {
+            continue;
+        }
+        if !matches!(token.kind, Error(_)) {
+            let position = source.position(token.char_range.start, opt.physical);
+            println!("{}: {}", position, token);
+        }
+        for diagnostic in lexer.take_diagnostics() {
+            emitter.emit(source, &diagnostic)?;
+        }
         stats.track(&token);
     }
+    Ok(())
+}
 
-    println!(
-        "{} error{} generated.",
-        stats.errors,
-        if let 0 | 1 = stats.errors { "" } else { "s" }
-    );
+/// Lexes `source` after preprocessing.
+///
+/// Tokens are reported where they are spelled, followed by the macro
+/// expansions they come from.
+fn preprocess(
+    opt: &Opt,
+    source: &SourceFile,
+    stats: &mut Statistics,
+    emitter: &mut impl Emitter,
+) -> Result<()> {
+    let mut preprocessor = Preprocessor::new(source.clone())
+        .with_dialect(opt.std)
+        .with_data_model(opt.model)
+        .with_trigraphs(opt.trigraphs);
+    for path in &opt.include_paths {
+        preprocessor = preprocessor.with_include_path(path.clone());
+    }
+    loop {
+        let token = preprocessor.next();
+        for error in preprocessor.take_errors() {
+            emitter.emit(&error.location.file, &error.diagnostic())?;
+            emit_expansions(emitter, &error.location)?;
+            if !error.kind.is_warning() {
+                stats.errors += 1;
+            }
+        }
+        let token = match token {
+            Some(token) => token,
+            None => break,
+        };
+        let location = &token.location;
+        if let Error(_) = token.kind {
+            let file = &location.file;
+            let (byte_range, char_range) =
+                (location.byte_range.clone(), location.char_range.clone());
+            let spelled = Token::new(token.kind, byte_range, char_range, file.src.as_str());
+            if let Some(diagnostic) = Diagnostic::from_token(&spelled) {
+                emitter.emit(file, &diagnostic)?;
+            }
+            emit_expansions(emitter, location)?;
+        } else {
+            let position = location
+                .file
+                .position(location.char_range.start, opt.physical);
+            println!("{}: {}", position, token);
+        }
+        stats.track(&token.token());
+    }
+    Ok(())
+}
 
-    println!("\nStatistics: \n{}", stats);
+/// Emits a note for each macro expansion `location` comes from.
+fn emit_expansions(emitter: &mut impl Emitter, location: &Location) -> Result<()> {
+    for expansion in location.expansions() {
+        let call_site = &expansion.call_site;
+        let message = locale::message("expansion", &[("name", &expansion.name)]);
+        let (byte_range, char_range) = (call_site.byte_range.clone(), call_site.char_range.clone());
+        let primary = Label::new(byte_range, char_range, String::new());
+        emitter.emit(
+            &call_site.file,
+            &Diagnostic::new(Severity::Note, message, primary),
+        )?;
+    }
     Ok(())
 }
@@ -0,0 +1,1949 @@
+//! A C preprocessor (translation phase 4) over the tokens of [`LexMode::Preprocessing`].
+//!
+//! [`Preprocessor`] reads a source file and the files it includes, executes
+//! the directives of Section 6.10 and expands macros. Every token it
+//! returns has been converted as by [`Lexer::convert_token`], and carries a
+//! [`Location`] that traces it back through the macro expansions it comes
+//! from.
+
+use crate::diagnostics::{Diagnostic, Label, Severity};
+use crate::dialect::Dialect;
+use crate::lexer::{logical_spelling, source_ranges, LexMode, Lexer};
+use crate::literal::{decode_literal, integer_value, DataModel};
+use crate::locale;
+use crate::source::{LineMarker, PresumedLocation, SourceFile};
+use crate::token::*;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::iter::Peekable;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::time::{SystemTime, UNIX_EPOCH};
+use std::vec;
+
+/// How deeply `#include` directives may nest.
+pub const MAX_INCLUDE_DEPTH: usize = 200;
+
+/// Where a preprocessing token is spelled, and the macro expansion that
+/// produced it, if any.
+///
+/// Tokens from the replacement list of a macro are spelled in its
+/// definition; tokens from its arguments keep the location they have at
+/// the macro call.
+#[derive(Clone)]
+pub struct Location {
+    /// The file the token is spelled in
+    pub file: Rc<SourceFile>,
+    /// Byte offsets of the token in the file
+    pub byte_range: Range<usize>,
+    /// Char offsets of the token in the file
+    pub char_range: Range<usize>,
+    /// The macro expansion the token comes from
+    pub expansion: Option<Rc<Expansion>>,
+}
+
+/// A use of a macro.
+#[derive(Clone, Debug)]
+pub struct Expansion {
+    /// The name of the macro
+    pub name: String,
+    /// The location of the macro name at its use, which may come from
+    /// another expansion
+    pub call_site: Location,
+}
+
+impl Location {
+    /// Returns the zero-based line and column where the location begins.
+    pub fn line_column(&self) -> (usize, usize) {
+        self.file.lookup_line_column(self.char_range.start)
+    }
+
+    /// Returns the expansions the token comes from, innermost first.
+    pub fn expansions(&self) -> impl Iterator<Item = &Expansion> {
+        std::iter::successors(self.expansion.as_deref(), |expansion| {
+            expansion.call_site.expansion.as_deref()
+        })
+    }
+
+    /// Returns the location in the source text that the expansion history
+    /// leads to, i.e. the call site of the outermost expansion.
+    pub fn expansion_root(&self) -> &Location {
+        self.expansions()
+            .last()
+            .map_or(self, |expansion| &expansion.call_site)
+    }
+}
+
+impl fmt::Debug for Location {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}@{:?}", self.file.path.display(), self.byte_range)?;
+        for expansion in self.expansions() {
+            write!(f, " in {}", expansion.name)?;
+        }
+        Ok(())
+    }
+}
+
+/// A preprocessing token, owning its spelling since it may come from an
+/// included file, `#` or `##`.
+#[derive(Clone)]
+pub struct PpToken {
+    /// The category of the token
+    pub kind: TokenKind,
+    /// The logical spelling of the token
+    pub spelling: String,
+    pub location: Location,
+    /// Whether the token is the first one of a line
+    pub at_line_start: bool,
+    /// Whether whitespace or a comment precedes the token
+    pub has_leading_space: bool,
+    /// The macros that must not be expanded again from this token on
+    hide_set: Vec<String>,
+}
+
+impl PpToken {
+    /// Returns a [`Token`] whose source is the spelling of this one, e.g.
+    /// to track it in [`Statistics`](crate::Statistics).
+    pub fn token(&self) -> Token<'_> {
+        let len = self.spelling.chars().count();
+        let mut token = Token::new(self.kind, 0..self.spelling.len(), 0..len, &self.spelling);
+        token.at_line_start = self.at_line_start;
+        token.has_leading_space = self.has_leading_space;
+        token
+    }
+}
+
+impl fmt::Debug for PpToken {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "[{:?}: {:?}@{:?}]",
+            self.kind, self.spelling, self.location
+        )
+    }
+}
+
+impl fmt::Display for PpToken {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "[{:?}: {:?}]", self.kind, self.spelling)
+    }
+}
+
+/// Problems found while preprocessing.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum PpErrorKind {
+    /// A warning of the lexer, e.g. about a floating constant overflow
+    Lexer(Box<Diagnostic>),
+    /// An `#include` file that is found nowhere in the search paths
+    IncludeNotFound(String),
+    /// Deeper `#include` nesting than [`MAX_INCLUDE_DEPTH`]
+    IncludeDepthExceeded,
+    /// An `#include` directive without a header name
+    InvalidInclude,
+    /// A missing or non-identifier macro name, or `defined`
+    InvalidMacroName,
+    /// A malformed or duplicate parameter of a function-like macro
+    InvalidMacroParameters,
+    /// A macro defined again with a different replacement list
+    MacroRedefined(String),
+    /// A `#` not followed by a parameter in a function-like macro
+    InvalidStringify,
+    /// A `##` at either end of a replacement list
+    PasteAtEdge,
+    /// A `##` whose result, given here, is not a single token
+    InvalidPaste(String),
+    /// A function-like macro call without its closing parenthesis
+    UnterminatedMacroCall(String),
+    /// A function-like macro called with the wrong number of arguments
+    WrongArgumentCount {
+        name: String,
+        expected: usize,
+        found: usize,
+    },
+    /// A malformed `#if` or `#elif` expression
+    InvalidExpression,
+    /// A division by zero in an `#if` or `#elif` expression
+    DivisionByZero,
+    /// An `#elif`, `#else` or `#endif` without a matching `#if`
+    UnmatchedConditional(String),
+    /// An `#elif` or `#else` after `#else`
+    ConditionalAfterElse(String),
+    /// An `#if` without its `#endif` by the end of the file
+    UnterminatedConditional,
+    /// A `#line` directive not followed by a line number and an
+    /// optional file name
+    InvalidLineDirective,
+    /// A `_Pragma` operator not followed by a parenthesized string literal
+    InvalidPragma,
+    UnknownDirective(String),
+    /// The message of an `#error` directive
+    ErrorDirective(String),
+    /// The message of a `#warning` directive, since C23
+    WarningDirective(String),
+}
+
+impl PpErrorKind {
+    /// Returns `true` for problems that do not stop a compiler.
+    pub fn is_warning(&self) -> bool {
+        match self {
+            PpErrorKind::Lexer(diagnostic) => !diagnostic.is_error(),
+            PpErrorKind::WarningDirective(_) => true,
+            _ => false,
+        }
+    }
+
+    /// Returns the stable code of the problem in diagnostics.
+    pub fn code(&self) -> Option<&'static str> {
+        let code = match self {
+            PpErrorKind::Lexer(diagnostic) => return diagnostic.code,
+            PpErrorKind::IncludeNotFound(_) => "E0101",
+            PpErrorKind::IncludeDepthExceeded => "E0102",
+            PpErrorKind::InvalidInclude => "E0103",
+            PpErrorKind::InvalidMacroName => "E0104",
+            PpErrorKind::InvalidMacroParameters => "E0105",
+            PpErrorKind::MacroRedefined(_) => "E0106",
+            PpErrorKind::InvalidStringify => "E0107",
+            PpErrorKind::PasteAtEdge => "E0108",
+            PpErrorKind::InvalidPaste(_) => "E0109",
+            PpErrorKind::UnterminatedMacroCall(_) => "E0110",
+            PpErrorKind::WrongArgumentCount { .. } => "E0111",
+            PpErrorKind::InvalidExpression => "E0112",
+            PpErrorKind::DivisionByZero => "E0113",
+            PpErrorKind::UnmatchedConditional(_) => "E0114",
+            PpErrorKind::ConditionalAfterElse(_) => "E0115",
+            PpErrorKind::UnterminatedConditional => "E0116",
+            PpErrorKind::InvalidLineDirective => "E0117",
+            PpErrorKind::InvalidPragma => "E0118",
+            PpErrorKind::UnknownDirective(_) => "E0119",
+            PpErrorKind::ErrorDirective(_) => "E0120",
+            PpErrorKind::WarningDirective(_) => "W0101",
+        };
+        Some(code)
+    }
+}
+
+impl fmt::Display for PpErrorKind {
+    /// Writes the message of the problem in the current
+    /// [`Catalog`](crate::Catalog).
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let code = match self {
+            PpErrorKind::Lexer(diagnostic) => return f.write_str(&diagnostic.message),
+            kind => kind.code().unwrap(),
+        };
+        let message = match self {
+            PpErrorKind::IncludeNotFound(name)
+            | PpErrorKind::MacroRedefined(name)
+            | PpErrorKind::UnterminatedMacroCall(name) => locale::message(code, &[("name", name)]),
+            PpErrorKind::IncludeDepthExceeded => {
+                locale::message(code, &[("max", &MAX_INCLUDE_DEPTH)])
+            }
+            PpErrorKind::InvalidPaste(text)
+            | PpErrorKind::ErrorDirective(text)
+            | PpErrorKind::WarningDirective(text) => locale::message(code, &[("text", text)]),
+            PpErrorKind::WrongArgumentCount {
+                name,
+                expected,
+                found,
+            } => locale::message(
+                code,
+                &[("name", name), ("expected", expected), ("found", found)],
+            ),
+            PpErrorKind::UnmatchedConditional(directive)
+            | PpErrorKind::ConditionalAfterElse(directive)
+            | PpErrorKind::UnknownDirective(directive) => {
+                locale::message(code, &[("directive", directive)])
+            }
+            _ => locale::message(code, &[]),
+        };
+        f.write_str(&message)
+    }
+}
+
+/// A problem found while preprocessing, and where.
+#[derive(Clone, Debug)]
+pub struct PpError {
+    pub kind: PpErrorKind,
+    pub location: Location,
+}
+
+impl PpError {
+    /// Returns the diagnostic of the problem, whose spans are offsets into
+    /// `location.file`. The macro expansions it comes from are left out.
+    ///
+    /// The location of a [`PpErrorKind::Lexer`] problem is already that of
+    /// the primary span of its diagnostic, translated into the file.
+    pub fn diagnostic(&self) -> Diagnostic {
+        let location = &self.location;
+        let (byte_range, char_range) = (location.byte_range.clone(), location.char_range.clone());
+        if let PpErrorKind::Lexer(diagnostic) = &self.kind {
+            let primary = Label::new(byte_range, char_range, diagnostic.primary.message.clone());
+            return Diagnostic {
+                primary,
+                ..(**diagnostic).clone()
+            };
+        }
+        let severity = match self.kind.is_warning() {
+            true => Severity::Warning,
+            false => Severity::Error,
+        };
+        let primary = Label::new(byte_range, char_range, String::new());
+        let diagnostic = Diagnostic::new(severity, self.kind.to_string(), primary);
+        match self.kind.code() {
+            Some(code) => diagnostic.with_code(code),
+            None => diagnostic,
+        }
+    }
+}
+
+/// A macro definition.
+struct Macro {
+    /// The parameters of a function-like macro, ending with `__VA_ARGS__`
+    /// if it is variadic
+    params: Option<Vec<String>>,
+    variadic: bool,
+    body: Vec<PpToken>,
+}
+
+impl Macro {
+    /// Returns `true` if the definitions are the same, as required for a
+    /// redefinition (Section 6.10.3).
+    fn same_definition(&self, other: &Macro) -> bool {
+        self.params == other.params
+            && self.body.len() == other.body.len()
+            && self.body.iter().zip(&other.body).all(|(a, b)| {
+                a.spelling == b.spelling && a.has_leading_space == b.has_leading_space
+            })
+    }
+
+    fn param(&self, token: &PpToken) -> Option<usize> {
+        if token.kind != Ident {
+            return None;
+        }
+        let params = self.params.as_ref()?;
+        params.iter().position(|param| *param == token.spelling)
+    }
+}
+
+/// A token of a replacement list being substituted, or a placemarker
+/// standing for an empty argument around `##` (Section 6.10.3.3).
+enum Item {
+    Token(PpToken),
+    Placemarker,
+}
+
+/// An `#if` group of a file.
+struct Conditional {
+    /// The location of the `if`, `ifdef` or `ifndef`
+    location: Location,
+    /// Whether a group of the `#if` has been taken
+    taken: bool,
+    has_else: bool,
+}
+
+/// A file being read.
+struct Frame {
+    file: Rc<SourceFile>,
+    tokens: Peekable<vec::IntoIter<PpToken>>,
+    conditionals: Vec<Conditional>,
+}
+
+/// A preprocessor over a source file and the files it includes.
+///
+/// Iterate over it to get the preprocessed tokens, then call
+/// [`take_errors`](Preprocessor::take_errors) for the problems found.
+/// Tokens the lexer found erroneous, such as unterminated string literals,
+/// are returned as [`Error`] tokens.
+pub struct Preprocessor {
+    dialect: Dialect,
+    data_model: DataModel,
+    trigraphs: bool,
+    include_paths: Vec<PathBuf>,
+    /// The source file, until preprocessing starts
+    main: Option<SourceFile>,
+    frames: Vec<Frame>,
+    /// Tokens to read before those of the current file, in reverse order:
+    /// the results of expansions to rescan, and tokens read ahead
+    pending: Vec<PpToken>,
+    /// Whether only `pending` is read, as for a macro argument
+    isolated: bool,
+    macros: HashMap<String, Rc<Macro>>,
+    /// The files with `#pragma once`
+    once: HashSet<PathBuf>,
+    errors: Vec<PpError>,
+}
+
+impl Preprocessor {
+    /// Creates a preprocessor for `source`.
+    pub fn new(source: SourceFile) -> Self {
+        Self {
+            dialect: Dialect::default(),
+            data_model: DataModel::default(),
+            trigraphs: false,
+            include_paths: Vec::new(),
+            main: Some(source),
+            frames: Vec::new(),
+            pending: Vec::new(),
+            isolated: false,
+            macros: HashMap::new(),
+            once: HashSet::new(),
+            errors: Vec::new(),
+        }
+    }
+
+    /// Sets the revision of the C standard; the default is C99.
+    pub fn with_dialect(mut self, dialect: Dialect) -> Self {
+        self.dialect = dialect;
+        self
+    }
+
+    /// Sets the data model of the target; the default is LP64.
+    pub fn with_data_model(mut self, data_model: DataModel) -> Self {
+        self.data_model = data_model;
+        self
+    }
+
+    /// Enables trigraph replacement, which is off by default.
+    pub fn with_trigraphs(mut self, trigraphs: bool) -> Self {
+        self.trigraphs = trigraphs;
+        self
+    }
+
+    /// Appends a directory to search for `#include` files, like `-I`.
+    ///
+    /// `#include "..."` looks in the directory of the including file
+    /// first; `#include <...>` only looks in these directories.
+    pub fn with_include_path(mut self, path: PathBuf) -> Self {
+        self.include_paths.push(path);
+        self
+    }
+
+    /// Returns the problems found since the last call, leaving none behind.
+    pub fn take_errors(&mut self) -> Vec<PpError> {
+        std::mem::take(&mut self.errors)
+    }
+
+    fn error(&mut self, kind: PpErrorKind, location: &Location) {
+        self.errors.push(PpError {
+            kind,
+            location: location.clone(),
+        });
+    }
+
+    /// Reads the predefined macros, then the source file.
+    fn start(&mut self) {
+        if let Some(main) = self.main.take() {
+            self.push_file(main);
+            let predefined = self.predefined_macros();
+            self.push_file(SourceFile::new(PathBuf::from("<built-in>"), predefined));
+        }
+    }
+
+    /// Returns the definitions of the predefined macros (Section 6.10.8),
+    /// except `__FILE__` and `__LINE__`, which are expanded on the fly.
+    fn predefined_macros(&self) -> String {
+        let (date, time) = date_time(SystemTime::now());
+        let mut src = String::new();
+        src += &format!("#define __DATE__ \"{}\"\n", date);
+        src += &format!("#define __TIME__ \"{}\"\n", time);
+        src += "#define __STDC__ 1\n";
+        src += "#define __STDC_HOSTED__ 1\n";
+        if let Some(version) = self.dialect.stdc_version() {
+            src += &format!("#define __STDC_VERSION__ {}\n", version);
+        }
+        if self.dialect.has_unicode_literals() {
+            src += "#define __STDC_UTF_16__ 1\n";
+            src += "#define __STDC_UTF_32__ 1\n";
+        }
+        src
+    }
+
+    /// Lexes `file` and starts reading it.
+    fn push_file(&mut self, file: SourceFile) {
+        let file = Rc::new(file);
+        let mut lexer = Lexer::new(file.src.as_str())
+            .with_mode(LexMode::Preprocessing)
+            .with_dialect(self.dialect)
+            .with_trigraphs(self.trigraphs);
+        let mut tokens = Vec::new();
+        while let Some(token) = lexer.advance_token() {
+            if token.kind.is_trivia() {
+                continue;
+            }
+            tokens.push(PpToken {
+                kind: token.kind,
+                spelling: token.spelling().into_owned(),
+                location: Location {
+                    file: file.clone(),
+                    byte_range: token.byte_range,
+                    char_range: token.char_range,
+                    expansion: None,
+                },
+                at_line_start: token.at_line_start,
+                has_leading_space: token.has_leading_space,
+                hide_set: Vec::new(),
+            });
+        }
+        // Errors travel along with their tokens, which may be skipped.
+        for warning in lexer.take_diagnostics() {
+            if warning.is_error() {
+                continue;
+            }
+            let location = Location {
+                file: file.clone(),
+                byte_range: warning.primary.byte_range.clone(),
+                char_range: warning.primary.char_range.clone(),
+                expansion: None,
+            };
+            self.error(PpErrorKind::Lexer(Box::new(warning)), &location);
+        }
+        self.frames.push(Frame {
+            file,
+            tokens: tokens.into_iter().peekable(),
+            conditionals: Vec::new(),
+        });
+    }
+
+    /// Returns the next token before macro expansion, executing the
+    /// directives on the way.
+    fn next_raw(&mut self) -> Option<PpToken> {
+        if let Some(token) = self.pending.pop() {
+            return Some(token);
+        }
+        if self.isolated {
+            return None;
+        }
+        loop {
+            let frame = self.frames.last_mut()?;
+            if let Some(token) = frame.tokens.next() {
+                if token.at_line_start && token.kind == Punct(PunctKind::Hash) {
+                    self.directive();
+                    continue;
+                }
+                return Some(token);
+            }
+            let frame = self.frames.pop().unwrap();
+            for conditional in frame.conditionals {
+                self.error(PpErrorKind::UnterminatedConditional, &conditional.location);
+            }
+        }
+    }
+
+    /// Returns the token [`next_raw`](Preprocessor::next_raw) would return,
+    /// looking no further than the current file and stopping at directives,
+    /// which must not be executed by looking ahead.
+    fn peek_raw(&mut self) -> Option<&PpToken> {
+        if let Some(token) = self.pending.last() {
+            return Some(token);
+        }
+        if self.isolated {
+            return None;
+        }
+        let frame = self.frames.last_mut()?;
+        frame
+            .tokens
+            .peek()
+            .filter(|token| !(token.at_line_start && token.kind == Punct(PunctKind::Hash)))
+    }
+
+    /// Takes the rest of the current line of the current file.
+    fn rest_of_line(&mut self) -> Vec<PpToken> {
+        let mut tokens = Vec::new();
+        if let Some(frame) = self.frames.last_mut() {
+            while let Some(token) = frame.tokens.next_if(|token| !token.at_line_start) {
+                tokens.push(token);
+            }
+        }
+        tokens
+    }
+
+    /// Executes the directive whose `#` has just been read (Section 6.10).
+    fn directive(&mut self) {
+        let mut tokens = self.rest_of_line();
+        if tokens.is_empty() {
+            // The null directive
+            return;
+        }
+        let name = tokens.remove(0);
+        let kind = self.directive_kind(&name);
+        match kind {
+            DirectiveKind::Include => self.include(&name, tokens),
+            DirectiveKind::Define => self.define(&name, tokens),
+            DirectiveKind::Undef => self.undef(&name, tokens),
+            DirectiveKind::If | DirectiveKind::Ifdef | DirectiveKind::Ifndef => {
+                let taken = self.condition(kind, &name, tokens);
+                let frame = self.frames.last_mut().unwrap();
+                frame.conditionals.push(Conditional {
+                    location: name.location.clone(),
+                    taken,
+                    has_else: false,
+                });
+                if !taken {
+                    self.skip_group();
+                }
+            }
+            DirectiveKind::Else
+            | DirectiveKind::Elif
+            | DirectiveKind::Elifdef
+            | DirectiveKind::Elifndef => self.else_group(&name),
+            DirectiveKind::Endif => {
+                let frame = self.frames.last_mut().unwrap();
+                if frame.conditionals.pop().is_none() {
+                    let kind = PpErrorKind::UnmatchedConditional(name.spelling.clone());
+                    self.error(kind, &name.location);
+                }
+            }
+            DirectiveKind::Line => self.line(&name, tokens),
+            DirectiveKind::Error => {
+                self.error(PpErrorKind::ErrorDirective(text(&tokens)), &name.location)
+            }
+            DirectiveKind::Warning => {
+                self.error(PpErrorKind::WarningDirective(text(&tokens)), &name.location)
+            }
+            DirectiveKind::Pragma => self.pragma(&text(&tokens)),
+            DirectiveKind::Null | DirectiveKind::NonDirective => {
+                let kind = PpErrorKind::UnknownDirective(name.spelling.clone());
+                self.error(kind, &name.location);
+            }
+        }
+    }
+
+    /// Returns the kind of the directive named by `name`, the token after `#`.
+    fn directive_kind(&self, name: &PpToken) -> DirectiveKind {
+        match name.kind {
+            Ident => DirectiveKind::from_name(&name.spelling, self.dialect),
+            _ => DirectiveKind::NonDirective,
+        }
+    }
+
+    /// Handles an `#elif` or `#else` ending a group that has been taken.
+    fn else_group(&mut self, name: &PpToken) {
+        let frame = self.frames.last_mut().unwrap();
+        let conditional = match frame.conditionals.last_mut() {
+            Some(conditional) => conditional,
+            None => {
+                let kind = PpErrorKind::UnmatchedConditional(name.spelling.clone());
+                return self.error(kind, &name.location);
+            }
+        };
+        let after_else = conditional.has_else;
+        conditional.has_else |= name.spelling == "else";
+        if after_else {
+            let kind = PpErrorKind::ConditionalAfterElse(name.spelling.clone());
+            self.error(kind, &name.location);
+        }
+        self.skip_group();
+    }
+
+    /// Skips lines up to the next group of the innermost `#if` to take, or
+    /// up to its `#endif`.
+    fn skip_group(&mut self) {
+        let mut depth = 0;
+        loop {
+            let token = match self.frames.last_mut().and_then(|frame| frame.tokens.next()) {
+                Some(token) => token,
+                None => return,
+            };
+            if !token.at_line_start || token.kind != Punct(PunctKind::Hash) {
+                continue;
+            }
+            let mut tokens = self.rest_of_line();
+            if tokens.is_empty() {
+                continue;
+            }
+            let name = tokens.remove(0);
+            let kind = self.directive_kind(&name);
+            match kind {
+                DirectiveKind::If | DirectiveKind::Ifdef | DirectiveKind::Ifndef => depth += 1,
+                DirectiveKind::Endif if depth > 0 => depth -= 1,
+                _ if depth > 0 => (),
+                DirectiveKind::Endif => {
+                    self.frames.last_mut().unwrap().conditionals.pop();
+                    return;
+                }
+                DirectiveKind::Else => {
+                    let frame = self.frames.last_mut().unwrap();
+                    let conditional = frame.conditionals.last_mut().unwrap();
+                    let after_else = conditional.has_else;
+                    conditional.has_else = true;
+                    if after_else {
+                        let kind = PpErrorKind::ConditionalAfterElse(name.spelling.clone());
+                        self.error(kind, &name.location);
+                    } else if !conditional.taken {
+                        conditional.taken = true;
+                        return;
+                    }
+                }
+                DirectiveKind::Elif | DirectiveKind::Elifdef | DirectiveKind::Elifndef => {
+                    let frame = self.frames.last_mut().unwrap();
+                    let conditional = frame.conditionals.last().unwrap();
+                    if conditional.has_else {
+                        let kind = PpErrorKind::ConditionalAfterElse(name.spelling.clone());
+                        self.error(kind, &name.location);
+                    } else if !conditional.taken && self.condition(kind, &name, tokens) {
+                        let frame = self.frames.last_mut().unwrap();
+                        frame.conditionals.last_mut().unwrap().taken = true;
+                        return;
+                    }
+                }
+                _ => (),
+            }
+        }
+    }
+
+    /// Evaluates the condition of an `#if`, `#ifdef`, `#ifndef` or `#elif`
+    /// directive named `name`.
+    fn condition(&mut self, kind: DirectiveKind, name: &PpToken, tokens: Vec<PpToken>) -> bool {
+        let negated = match kind {
+            DirectiveKind::If | DirectiveKind::Elif => return self.evaluate(name, tokens),
+            DirectiveKind::Ifndef | DirectiveKind::Elifndef => true,
+            _ => false,
+        };
+        match tokens.first() {
+            Some(token) if token.kind == Ident => self.is_defined(&token.spelling) != negated,
+            token => {
+                let location = token.map_or(&name.location, |token| &token.location);
+                self.error(PpErrorKind::InvalidMacroName, &location.clone());
+                false
+            }
+        }
+    }
+
+    fn is_defined(&self, name: &str) -> bool {
+        self.macros.contains_key(name) || name == "__FILE__" || name == "__LINE__"
+    }
+
+    /// Evaluates the controlling expression of an `#if` or `#elif`
+    /// (Section 6.10.1).
+    fn evaluate(&mut self, name: &PpToken, tokens: Vec<PpToken>) -> bool {
+        let mut replaced = Vec::new();
+        let mut tokens = tokens.into_iter();
+        while let Some(token) = tokens.next() {
+            if token.kind != Ident || token.spelling != "defined" {
+                replaced.push(token);
+                continue;
+            }
+            let mut operand = tokens.next();
+            let parenthesized = matches!(&operand, Some(t) if t.kind == Punct(PunctKind::LParen));
+            if parenthesized {
+                operand = tokens.next();
+            }
+            let operand = match operand {
+                Some(operand) if operand.kind == Ident => operand,
+                operand => {
+                    let location = operand.map_or(token.location, |operand| operand.location);
+                    self.error(PpErrorKind::InvalidExpression, &location);
+                    return false;
+                }
+            };
+            if parenthesized {
+                match tokens.next() {
+                    Some(paren) if paren.kind == Punct(PunctKind::RParen) => (),
+                    _ => {
+                        self.error(PpErrorKind::InvalidExpression, &operand.location);
+                        return false;
+                    }
+                }
+            }
+            let defined = self.is_defined(&operand.spelling);
+            replaced.push(PpToken {
+                kind: PpNumber,
+                spelling: (defined as u8).to_string(),
+                ..token
+            });
+        }
+        let tokens = self.expand_list(replaced);
+        let mut expression = Expression {
+            tokens: &tokens,
+            pos: 0,
+            dialect: self.dialect,
+            data_model: self.data_model,
+        };
+        match expression.parse() {
+            Ok(value) => value.is_true(),
+            Err((kind, pos)) => {
+                let location = tokens
+                    .get(pos)
+                    .map_or(&name.location, |token| &token.location);
+                let location = location.clone();
+                self.error(kind, &location);
+                false
+            }
+        }
+    }
+
+    /// Executes an `#include` directive (Section 6.10.2).
+    fn include(&mut self, name: &PpToken, tokens: Vec<PpToken>) {
+        let tokens = match tokens.first() {
+            Some(token) if token.kind == HeaderName => tokens,
+            _ => self.expand_list(tokens),
+        };
+        let (header, quoted) = match header_name(&tokens) {
+            Some(header) => header,
+            None => return self.error(PpErrorKind::InvalidInclude, &name.location),
+        };
+        if self.frames.len() > MAX_INCLUDE_DEPTH {
+            return self.error(PpErrorKind::IncludeDepthExceeded, &name.location);
+        }
+        let file = self
+            .find_include(&header, quoted)
+            .and_then(|path| SourceFile::open(path).ok());
+        match file {
+            Some(file) if self.once.contains(&canonical_path(&file.path)) => (),
+            Some(file) => self.push_file(file),
+            None => self.error(PpErrorKind::IncludeNotFound(header), &name.location),
+        }
+    }
+
+    fn find_include(&self, header: &str, quoted: bool) -> Option<PathBuf> {
+        let current_dir = match quoted {
+            true => self
+                .frames
+                .last()
+                .and_then(|frame| frame.file.path.parent())
+                .map(Path::to_path_buf),
+            false => None,
+        };
+        current_dir
+            .iter()
+            .chain(&self.include_paths)
+            .map(|dir| dir.join(header))
+            .find(|path| path.is_file())
+    }
+
+    /// Executes a `#define` directive (Section 6.10.3).
+    fn define(&mut self, directive: &PpToken, tokens: Vec<PpToken>) {
+        let mut tokens = tokens.into_iter().peekable();
+        let name = match tokens.next() {
+            Some(name) if name.kind == Ident && name.spelling != "defined" => name,
+            name => {
+                let location = name.map_or(directive.location.clone(), |name| name.location);
+                return self.error(PpErrorKind::InvalidMacroName, &location);
+            }
+        };
+        let mut params = None;
+        let mut variadic = false;
+        if let Some(paren) = tokens
+            .next_if(|paren| paren.kind == Punct(PunctKind::LParen) && !paren.has_leading_space)
+        {
+            match parse_params(&mut tokens) {
+                Ok((names, is_variadic)) => {
+                    params = Some(names);
+                    variadic = is_variadic;
+                }
+                Err(location) => {
+                    let location = location.unwrap_or(paren.location);
+                    return self.error(PpErrorKind::InvalidMacroParameters, &location);
+                }
+            }
+        }
+        let mut body: Vec<PpToken> = tokens.collect();
+        if let Some(first) = body.first_mut() {
+            first.has_leading_space = false;
+        }
+        let mac = Macro {
+            params,
+            variadic,
+            body,
+        };
+
+        let is_paste = |token: Option<&PpToken>| matches!(token, Some(token) if token.kind == Punct(PunctKind::HashHash));
+        if is_paste(mac.body.first()) || is_paste(mac.body.last()) {
+            return self.error(PpErrorKind::PasteAtEdge, &name.location);
+        }
+        if mac.params.is_some() {
+            for (i, token) in mac.body.iter().enumerate() {
+                let operand = mac.body.get(i + 1).and_then(|token| mac.param(token));
+                if token.kind == Punct(PunctKind::Hash) && operand.is_none() {
+                    return self.error(PpErrorKind::InvalidStringify, &token.location.clone());
+                }
+            }
+        }
+
+        if let Some(old) = self.macros.get(&name.spelling) {
+            if !old.same_definition(&mac) {
+                let kind = PpErrorKind::MacroRedefined(name.spelling.clone());
+                self.error(kind, &name.location);
+            }
+        }
+        self.macros.insert(name.spelling, Rc::new(mac));
+    }
+
+    /// Executes an `#undef` directive.
+    fn undef(&mut self, directive: &PpToken, tokens: Vec<PpToken>) {
+        match tokens.first() {
+            Some(name) if name.kind == Ident && name.spelling != "defined" => {
+                self.macros.remove(&name.spelling);
+            }
+            name => {
+                let location = name.map_or(&directive.location, |name| &name.location);
+                self.error(PpErrorKind::InvalidMacroName, &location.clone());
+            }
+        }
+    }
+
+    /// Executes a `#line` directive (Section 6.10.4), recording its effect
+    /// in the file, where diagnostics find it too.
+    fn line(&mut self, directive: &PpToken, tokens: Vec<PpToken>) {
+        // The line number applies to the line after the directive.
+        let last = &tokens.last().unwrap_or(directive).location;
+        let line = last.file.lookup_line_column(last.char_range.end - 1).0 + 1;
+        let tokens = self.expand_list(tokens);
+        let args: Vec<_> = tokens
+            .iter()
+            .map(|token| (token.kind, token.spelling.as_str()))
+            .collect();
+        match line_directive_args(&args) {
+            Some((presumed_line, path)) => {
+                let frame = self.frames.last().unwrap();
+                frame.file.add_line_marker(LineMarker {
+                    line,
+                    presumed_line,
+                    path,
+                });
+            }
+            None => self.error(PpErrorKind::InvalidLineDirective, &directive.location),
+        }
+    }
+
+    /// Executes a pragma. Only `once` is meaningful to the preprocessor;
+    /// other pragmas are ignored.
+    fn pragma(&mut self, text: &str) {
+        if text == "once" {
+            if let Some(frame) = self.frames.last() {
+                self.once.insert(canonical_path(&frame.file.path));
+            }
+        }
+    }
+
+    /// Executes a `_Pragma` operator whose name has just been read
+    /// (Section 6.10.9).
+    fn pragma_operator(&mut self, name: &PpToken) {
+        let paren = self.next_raw();
+        let operand = self.next_raw();
+        let close = self.next_raw();
+        match (&paren, &operand, &close) {
+            (Some(paren), Some(operand), Some(close))
+                if paren.kind == Punct(PunctKind::LParen)
+                    && matches!(operand.kind, StrLit(Encoding::Plain | Encoding::Wide))
+                    && close.kind == Punct(PunctKind::RParen) =>
+            {
+                let text = unquote(operand.spelling.trim_start_matches('L'));
+                self.pragma(text.trim());
+            }
+            _ => self.error(PpErrorKind::InvalidPragma, &name.location),
+        }
+    }
+
+    /// Expands `tokens` on their own, as for a macro argument or a directive.
+    fn expand_list(&mut self, tokens: Vec<PpToken>) -> Vec<PpToken> {
+        let pending = std::mem::replace(&mut self.pending, tokens.into_iter().rev().collect());
+        let isolated = std::mem::replace(&mut self.isolated, true);
+        let mut expanded = Vec::new();
+        while let Some(token) = self.next_raw() {
+            if !self.expand(&token) {
+                expanded.push(token);
+            }
+        }
+        self.pending = pending;
+        self.isolated = isolated;
+        expanded
+    }
+
+    /// Expands the macro named by `token`, if any, leaving the result to
+    /// be rescanned (Section 6.10.3.4). Returns `false` if `token` is not
+    /// a macro call.
+    fn expand(&mut self, token: &PpToken) -> bool {
+        if token.kind != Ident || token.hide_set.contains(&token.spelling) {
+            return false;
+        }
+        let expansion = || {
+            Some(Rc::new(Expansion {
+                name: token.spelling.clone(),
+                call_site: token.location.clone(),
+            }))
+        };
+        let (kind, spelling) = match token.spelling.as_str() {
+            "__LINE__" => (PpNumber, (presumed(&token.location).line + 1).to_string()),
+            "__FILE__" => (
+                StrLit(Encoding::Plain),
+                quote(&presumed(&token.location).path),
+            ),
+            _ => (Ident, String::new()),
+        };
+        if kind != Ident {
+            let mut result = token.clone();
+            result.kind = kind;
+            result.spelling = spelling;
+            result.location.expansion = expansion();
+            self.pending.push(result);
+            return true;
+        }
+
+        let mac = match self.macros.get(&token.spelling) {
+            Some(mac) => mac.clone(),
+            None => return false,
+        };
+        let mut hide_set = token.hide_set.clone();
+        let mut args = Vec::new();
+        if let Some(params) = &mac.params {
+            match self.peek_raw() {
+                Some(paren) if paren.kind == Punct(PunctKind::LParen) => self.next_raw(),
+                _ => return false,
+            };
+            let rparen;
+            (args, rparen) = match self.collect_args(&mac) {
+                Some(args) => args,
+                None => {
+                    let kind = PpErrorKind::UnterminatedMacroCall(token.spelling.clone());
+                    self.error(kind, &token.location);
+                    return true;
+                }
+            };
+            let (expected, found) = (params.len(), args.len());
+            if expected == 0 && found == 1 && args[0].is_empty() {
+                args.clear();
+            } else if mac.variadic && found + 1 == expected {
+                args.push(Vec::new());
+            } else if found != expected {
+                let name = token.spelling.clone();
+                let kind = PpErrorKind::WrongArgumentCount {
+                    name,
+                    expected,
+                    found,
+                };
+                self.error(kind, &token.location);
+                return true;
+            }
+            hide_set.retain(|name| rparen.hide_set.contains(name));
+        }
+        hide_set.push(token.spelling.clone());
+
+        let expansion = expansion().unwrap();
+        let mut tokens = self.substitute(&mac, &args, &expansion);
+        for result in &mut tokens {
+            for name in &hide_set {
+                if !result.hide_set.contains(name) {
+                    result.hide_set.push(name.clone());
+                }
+            }
+            result.at_line_start = false;
+        }
+        if let Some(first) = tokens.first_mut() {
+            first.at_line_start = token.at_line_start;
+            first.has_leading_space = token.has_leading_space;
+        }
+        self.pending.extend(tokens.into_iter().rev());
+        true
+    }
+
+    /// Collects the arguments of a call to `mac` whose `(` has just been
+    /// read, and returns them with the closing `)`.
+    fn collect_args(&mut self, mac: &Macro) -> Option<(Vec<Vec<PpToken>>, PpToken)> {
+        let params = mac.params.as_ref().map_or(0, Vec::len);
+        let mut args = vec![Vec::new()];
+        let mut depth = 0;
+        loop {
+            let token = self.next_raw()?;
+            match token.kind {
+                Punct(PunctKind::LParen) => depth += 1,
+                Punct(PunctKind::RParen) if depth == 0 => return Some((args, token)),
+                Punct(PunctKind::RParen) => depth -= 1,
+                Punct(PunctKind::Comma)
+                    if depth == 0 && !(mac.variadic && args.len() == params) =>
+                {
+                    args.push(Vec::new());
+                    continue;
+                }
+                _ => (),
+            }
+            args.last_mut().unwrap().push(token);
+        }
+    }
+
+    /// Substitutes `args` for the parameters in the replacement list of
+    /// `mac`, and executes `#` and `##` (Sections 6.10.3.1 to 6.10.3.3).
+    fn substitute(
+        &mut self,
+        mac: &Macro,
+        args: &[Vec<PpToken>],
+        expansion: &Rc<Expansion>,
+    ) -> Vec<PpToken> {
+        let body = &mac.body;
+        let is_paste =
+            |i: usize| matches!(body.get(i), Some(t) if t.kind == Punct(PunctKind::HashHash));
+        let mut expanded_args: Vec<Option<Vec<PpToken>>> = vec![None; args.len()];
+        let mut items = Vec::new();
+        let mut i = 0;
+        while i < body.len() {
+            if is_paste(i) {
+                let (rhs, len) = operand(mac, args, expansion, i + 1);
+                let lhs = items.pop().unwrap_or(Item::Placemarker);
+                let mut rhs = rhs.into_iter();
+                let pasted = self.paste(lhs, rhs.next().unwrap());
+                items.extend(pasted);
+                items.extend(rhs);
+                i += 1 + len;
+                continue;
+            }
+            match mac.param(&body[i]) {
+                Some(param) if !is_paste(i + 1) => {
+                    if expanded_args[param].is_none() {
+                        expanded_args[param] = Some(self.expand_list(args[param].clone()));
+                    }
+                    let expanded = expanded_args[param].as_ref().unwrap();
+                    let mut tokens = expanded.iter().cloned();
+                    if let Some(mut first) = tokens.next() {
+                        first.has_leading_space = body[i].has_leading_space;
+                        items.push(Item::Token(first));
+                    }
+                    items.extend(tokens.map(Item::Token));
+                    i += 1;
+                }
+                _ => {
+                    let (tokens, len) = operand(mac, args, expansion, i);
+                    items.extend(tokens);
+                    i += len;
+                }
+            }
+        }
+        items
+            .into_iter()
+            .filter_map(|item| match item {
+                Item::Token(token) => Some(token),
+                Item::Placemarker => None,
+            })
+            .collect()
+    }
+
+    /// Pastes two tokens with `##`. If the result is not a single token,
+    /// both are kept.
+    fn paste(&mut self, lhs: Item, rhs: Item) -> Vec<Item> {
+        let (lhs, rhs) = match (lhs, rhs) {
+            (Item::Placemarker, rhs) => return vec![rhs],
+            (lhs, Item::Placemarker) => return vec![lhs],
+            (Item::Token(lhs), Item::Token(rhs)) => (lhs, rhs),
+        };
+        let spelling = format!("{}{}", lhs.spelling, rhs.spelling);
+        let mut lexer = Lexer::new(&spelling)
+            .with_mode(LexMode::Preprocessing)
+            .with_dialect(self.dialect);
+        match lexer.advance_token() {
+            Some(token) if !token.kind.is_trivia() && token.byte_range.end == spelling.len() => {
+                let mut pasted = lhs;
+                pasted.kind = token.kind;
+                for name in rhs.hide_set {
+                    if !pasted.hide_set.contains(&name) {
+                        pasted.hide_set.push(name);
+                    }
+                }
+                pasted.spelling = spelling;
+                vec![Item::Token(pasted)]
+            }
+            _ => {
+                self.error(PpErrorKind::InvalidPaste(spelling), &lhs.location);
+                vec![Item::Token(lhs), Item::Token(rhs)]
+            }
+        }
+    }
+
+    /// Converts a preprocessing token to a token (translation phase 7).
+    /// Returns the location in the file of `range`, a byte range in the
+    /// spelling of `token`, or that of the whole token if it is not spelled
+    /// as such in the file, e.g. after `##` or as `__LINE__`.
+    fn spelling_location(&self, token: &PpToken, range: Range<usize>) -> Location {
+        let location = &token.location;
+        let src = location.file.src.as_str();
+        if logical_spelling(&src[location.byte_range.clone()], self.trigraphs) != token.spelling {
+            return location.clone();
+        }
+        let begin = (location.byte_range.start, location.char_range.start);
+        let (byte_range, char_range) = source_ranges(src, self.trigraphs, begin.0, begin.1, range);
+        Location {
+            byte_range,
+            char_range,
+            ..location.clone()
+        }
+    }
+
+    fn convert(&mut self, mut token: PpToken) -> PpToken {
+        let (kind, warnings) = {
+            let mut lexer = Lexer::new(&token.spelling)
+                .with_dialect(self.dialect)
+                .with_data_model(self.data_model);
+            let kind = lexer.convert_token(&token.token()).kind;
+            (kind, lexer.take_diagnostics())
+        };
+        for warning in warnings.into_iter().filter(|d| !d.is_error()) {
+            let location = self.spelling_location(&token, warning.primary.byte_range.clone());
+            self.error(PpErrorKind::Lexer(Box::new(warning)), &location);
+        }
+        token.kind = kind;
+        token
+    }
+}
+
+impl Iterator for Preprocessor {
+    type Item = PpToken;
+
+    fn next(&mut self) -> Option<PpToken> {
+        self.start();
+        loop {
+            let token = self.next_raw()?;
+            if token.kind == Ident && token.spelling == "_Pragma" && self.dialect >= Dialect::C99 {
+                self.pragma_operator(&token);
+                continue;
+            }
+            if !self.expand(&token) {
+                return Some(self.convert(token));
+            }
+        }
+    }
+}
+
+/// Returns the item at `body[i]` before expansion, i.e. an argument as
+/// given, a stringified argument or a token of the replacement list, and
+/// the number of tokens it takes in `body`.
+fn operand(
+    mac: &Macro,
+    args: &[Vec<PpToken>],
+    expansion: &Rc<Expansion>,
+    i: usize,
+) -> (Vec<Item>, usize) {
+    let body = &mac.body;
+    let located = |token: &PpToken| {
+        let mut token = token.clone();
+        token.location.expansion = Some(expansion.clone());
+        token
+    };
+    if mac.params.is_some() && body[i].kind == Punct(PunctKind::Hash) {
+        if let Some(param) = body.get(i + 1).and_then(|token| mac.param(token)) {
+            let string = stringify(located(&body[i]), &args[param]);
+            return (vec![Item::Token(string)], 2);
+        }
+    }
+    match mac.param(&body[i]) {
+        Some(param) if args[param].is_empty() => (vec![Item::Placemarker], 1),
+        Some(param) => (args[param].iter().cloned().map(Item::Token).collect(), 1),
+        None => (vec![Item::Token(located(&body[i]))], 1),
+    }
+}
+
+/// Returns the string literal that `#` makes of `arg` (Section 6.10.3.2).
+fn stringify(hash: PpToken, arg: &[PpToken]) -> PpToken {
+    let mut spelling = String::from("\"");
+    for (i, token) in arg.iter().enumerate() {
+        if i > 0 && token.has_leading_space {
+            spelling.push(' ');
+        }
+        match token.kind {
+            StrLit(_) | Const(Char(_)) => {
+                for c in token.spelling.chars() {
+                    if c == '"' || c == '\\' {
+                        spelling.push('\\');
+                    }
+                    spelling.push(c);
+                }
+            }
+            _ => spelling += &token.spelling,
+        }
+    }
+    spelling.push('"');
+    PpToken {
+        kind: StrLit(Encoding::Plain),
+        spelling,
+        ..hash
+    }
+}
+
+/// Parses the parameters of a function-like macro up to the closing `)`.
+/// On error, returns the location of the offending token, if any.
+fn parse_params(
+    tokens: &mut impl Iterator<Item = PpToken>,
+) -> Result<(Vec<String>, bool), Option<Location>> {
+    let mut params: Vec<String> = Vec::new();
+    let mut variadic = false;
+    loop {
+        let token = tokens.next().ok_or(None)?;
+        match token.kind {
+            Punct(PunctKind::RParen) if params.is_empty() => return Ok((params, false)),
+            Punct(PunctKind::Ellipsis) => {
+                params.push("__VA_ARGS__".to_string());
+                variadic = true;
+            }
+            Ident if token.spelling != "__VA_ARGS__" && !params.contains(&token.spelling) => {
+                params.push(token.spelling)
+            }
+            _ => return Err(Some(token.location)),
+        }
+        let separator = tokens.next().ok_or(None)?;
+        match separator.kind {
+            Punct(PunctKind::RParen) => return Ok((params, variadic)),
+            Punct(PunctKind::Comma) if !variadic => (),
+            _ => return Err(Some(separator.location)),
+        }
+    }
+}
+
+/// Returns the file name of an `#include` directive and whether it is
+/// quoted, from a header name, a string literal, or tokens between `<`
+/// and `>`.
+fn header_name(tokens: &[PpToken]) -> Option<(String, bool)> {
+    let first = tokens.first()?;
+    match first.kind {
+        HeaderName | StrLit(Encoding::Plain) => {
+            let name = &first.spelling[1..first.spelling.len() - 1];
+            Some((name.to_string(), first.spelling.starts_with('"')))
+        }
+        Punct(PunctKind::Less) => {
+            let close = tokens
+                .iter()
+                .position(|token| token.kind == Punct(PunctKind::Greater))?;
+            let name = text(&tokens[1..close]);
+            Some((name, false))
+        }
+        _ => None,
+    }
+}
+
+/// Returns the spellings of `tokens` separated by single spaces where
+/// there is whitespace.
+fn text(tokens: &[PpToken]) -> String {
+    let mut text = String::new();
+    for (i, token) in tokens.iter().enumerate() {
+        if i > 0 && token.has_leading_space {
+            text.push(' ');
+        }
+        text += &token.spelling;
+    }
+    text
+}
+
+/// Returns the location of `location` as set by `#line` directives, which
+/// is where the macros it comes from are expanded.
+fn presumed(location: &Location) -> PresumedLocation {
+    let root = location.expansion_root();
+    root.file.lookup_presumed(root.char_range.start)
+}
+
+/// Returns the effect of a `#line` directive lexed in [`LexMode::Normal`]
+/// from `src`, given its [`Directive`] head followed by the tokens after it
+/// on its line, or `None` if the directive is invalid.
+pub(crate) fn line_marker(src: &str, tokens: &[Token]) -> Option<LineMarker> {
+    let last = tokens.last()?;
+    let line = src[..last.byte_range.end - 1].matches('\n').count() + 1;
+    let args = &tokens[1..];
+    let spellings: Vec<_> = args.iter().map(|token| token.spelling()).collect();
+    let args: Vec<_> = args
+        .iter()
+        .zip(&spellings)
+        .map(|(token, spelling)| (token.kind, spelling.as_ref()))
+        .collect();
+    let (presumed_line, path) = line_directive_args(&args)?;
+    Some(LineMarker {
+        line,
+        presumed_line,
+        path,
+    })
+}
+
+/// Returns the line number and the file name, if any, given by the kinds
+/// and spellings of the tokens of a `#line` directive after macro
+/// expansion (Section 6.10.4).
+fn line_directive_args(args: &[(TokenKind, &str)]) -> Option<(usize, Option<String>)> {
+    let number = match args.first()? {
+        (PpNumber, digits) | (Const(ConstKind::Integer), digits)
+            if digits.bytes().all(|b| b.is_ascii_digit()) =>
+        {
+            digits
+                .parse::<usize>()
+                .ok()
+                .filter(|&n| (1..=2147483647).contains(&n))?
+        }
+        _ => return None,
+    };
+    match args {
+        [_] => Some((number, None)),
+        [_, (StrLit(Encoding::Plain), path)] => Some((number, Some(unquote(path)))),
+        _ => None,
+    }
+}
+
+/// Returns the contents of a plain string literal, undoing the escapes of
+/// `\"` and `\\` only.
+fn unquote(spelling: &str) -> String {
+    let contents = &spelling[1..spelling.len() - 1];
+    let mut result = String::new();
+    let mut chars = contents.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => match chars.next() {
+                Some(c @ ('"' | '\\')) => result.push(c),
+                Some(c) => {
+                    result.push('\\');
+                    result.push(c);
+                }
+                None => result.push('\\'),
+            },
+            c => result.push(c),
+        }
+    }
+    result
+}
+
+/// Returns a plain string literal of `s`.
+fn quote(s: &str) -> String {
+    let mut literal = String::from("\"");
+    for c in s.chars() {
+        if c == '"' || c == '\\' {
+            literal.push('\\');
+        }
+        literal.push(c);
+    }
+    literal.push('"');
+    literal
+}
+
+/// Identifies a file for `#pragma once`.
+fn canonical_path(path: &Path) -> PathBuf {
+    path.canonicalize().unwrap_or_else(|_| path.to_path_buf())
+}
+
+/// Returns the values of `__DATE__` and `__TIME__` for `now`, in UTC.
+fn date_time(now: SystemTime) -> (String, String) {
+    const MONTHS: [&str; 12] = [
+        "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+    ];
+    let secs = now.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+    let (days, secs) = ((secs / 86400) as i64, secs % 86400);
+    // Converts days since 1970-01-01 to a civil date, after Howard Hinnant.
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + (month <= 2) as i64;
+    let date = format!("{} {:2} {}", MONTHS[month as usize - 1], day, year);
+    let time = format!("{:02}:{:02}:{:02}", secs / 3600, secs / 60 % 60, secs % 60);
+    (date, time)
+}
+
+/// A value of an `#if` expression, of type `intmax_t` or `uintmax_t`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Value {
+    bits: u64,
+    unsigned: bool,
+}
+
+impl Value {
+    fn int(value: i64) -> Self {
+        Value {
+            bits: value as u64,
+            unsigned: false,
+        }
+    }
+
+    fn is_true(self) -> bool {
+        self.bits != 0
+    }
+}
+
+type ExprResult = Result<Value, (PpErrorKind, usize)>;
+
+/// A parser evaluating the tokens of an `#if` expression after macro
+/// expansion. Errors carry the index of the offending token.
+struct Expression<'t> {
+    tokens: &'t [PpToken],
+    pos: usize,
+    dialect: Dialect,
+    data_model: DataModel,
+}
+
+impl Expression<'_> {
+    fn parse(&mut self) -> ExprResult {
+        let value = self.conditional(true)?;
+        match self.pos < self.tokens.len() {
+            true => Err((PpErrorKind::InvalidExpression, self.pos)),
+            false => Ok(value),
+        }
+    }
+
+    fn eat(&mut self, kind: PunctKind) -> bool {
+        let found = matches!(self.tokens.get(self.pos), Some(t) if t.kind == Punct(kind));
+        self.pos += found as usize;
+        found
+    }
+
+    /// Parses a conditional expression; nothing is evaluated, i.e. no
+    /// division by zero is reported, unless `eval`.
+    fn conditional(&mut self, eval: bool) -> ExprResult {
+        let condition = self.binary(1, eval)?;
+        if !self.eat(PunctKind::Question) {
+            return Ok(condition);
+        }
+        let then = self.conditional(eval && condition.is_true())?;
+        if !self.eat(PunctKind::Colon) {
+            return Err((PpErrorKind::InvalidExpression, self.pos));
+        }
+        let otherwise = self.conditional(eval && !condition.is_true())?;
+        let value = if condition.is_true() { then } else { otherwise };
+        Ok(Value {
+            bits: value.bits,
+            unsigned: then.unsigned || otherwise.unsigned,
+        })
+    }
+
+    /// Parses binary operators of precedence `min_precedence` or higher.
+    fn binary(&mut self, min_precedence: u8, eval: bool) -> ExprResult {
+        let mut lhs = self.unary(eval)?;
+        loop {
+            let op = match self.tokens.get(self.pos).map(|token| token.kind) {
+                Some(Punct(op)) => op,
+                _ => return Ok(lhs),
+            };
+            let precedence = match precedence(op) {
+                Some(precedence) if precedence >= min_precedence => precedence,
+                _ => return Ok(lhs),
+            };
+            let op_pos = self.pos;
+            self.pos += 1;
+            let rhs_eval = match op {
+                PunctKind::AmpAmp => eval && lhs.is_true(),
+                PunctKind::PipePipe => eval && !lhs.is_true(),
+                _ => eval,
+            };
+            let rhs = self.binary(precedence + 1, rhs_eval)?;
+            lhs = binary_op(op, lhs, rhs, eval).map_err(|kind| (kind, op_pos))?;
+        }
+    }
+
+    fn unary(&mut self, eval: bool) -> ExprResult {
+        let pos = self.pos;
+        let token = match self.tokens.get(pos) {
+            Some(token) => token,
+            None => return Err((PpErrorKind::InvalidExpression, pos)),
+        };
+        self.pos += 1;
+        match token.kind {
+            Punct(PunctKind::Plus) => self.unary(eval),
+            Punct(PunctKind::Minus) => self.unary(eval).map(|value| Value {
+                bits: value.bits.wrapping_neg(),
+                ..value
+            }),
+            Punct(PunctKind::Tilde) => self.unary(eval).map(|value| Value {
+                bits: !value.bits,
+                ..value
+            }),
+            Punct(PunctKind::Bang) => self
+                .unary(eval)
+                .map(|value| Value::int(!value.is_true() as i64)),
+            Punct(PunctKind::LParen) => {
+                let value = self.conditional(eval)?;
+                match self.eat(PunctKind::RParen) {
+                    true => Ok(value),
+                    false => Err((PpErrorKind::InvalidExpression, self.pos)),
+                }
+            }
+            PpNumber => match integer_value(&token.spelling, self.data_model) {
+                Ok(value) => Ok(Value {
+                    bits: value.value as u64,
+                    unsigned: !value.ty.is_signed(),
+                }),
+                Err(_) => Err((PpErrorKind::InvalidExpression, pos)),
+            },
+            Const(Char(_)) => char_value(&token.spelling, self.dialect, self.data_model)
+                .ok_or((PpErrorKind::InvalidExpression, pos)),
+            // Identifiers left after macro expansion are 0, except the
+            // keywords `true` and `false` of C23.
+            Ident => Ok(Value::int(
+                (self.dialect >= Dialect::C23 && token.spelling == "true") as i64,
+            )),
+            _ => Err((PpErrorKind::InvalidExpression, pos)),
+        }
+    }
+}
+
+/// Returns the precedence of a binary operator, from 1 for `||` to 10 for
+/// multiplicative ones.
+fn precedence(op: PunctKind) -> Option<u8> {
+    use PunctKind::*;
+    match op {
+        PipePipe => Some(1),
+        AmpAmp => Some(2),
+        Pipe => Some(3),
+        Caret => Some(4),
+        Amp => Some(5),
+        EqualEqual | BangEqual => Some(6),
+        Less | Greater | LessEqual | GreaterEqual => Some(7),
+        LessLess | GreaterGreater => Some(8),
+        Plus | Minus => Some(9),
+        Star | Slash | Percent => Some(10),
+        _ => None,
+    }
+}
+
+/// Applies a binary operator after the usual arithmetic conversions.
+fn binary_op(op: PunctKind, lhs: Value, rhs: Value, eval: bool) -> Result<Value, PpErrorKind> {
+    use PunctKind::*;
+    let unsigned = lhs.unsigned || rhs.unsigned;
+    let (a, b) = (lhs.bits, rhs.bits);
+    let (sa, sb) = (a as i64, b as i64);
+    let compare = |ordering: fn(std::cmp::Ordering) -> bool| {
+        let result = match unsigned {
+            true => a.cmp(&b),
+            false => sa.cmp(&sb),
+        };
+        Value::int(ordering(result) as i64)
+    };
+    let arithmetic = |bits: u64| Value { bits, unsigned };
+    Ok(match op {
+        Star => arithmetic(a.wrapping_mul(b)),
+        Slash | Percent if b == 0 => match eval {
+            true => return Err(PpErrorKind::DivisionByZero),
+            false => arithmetic(0),
+        },
+        Slash if unsigned => arithmetic(a / b),
+        Slash => arithmetic(sa.wrapping_div(sb) as u64),
+        Percent if unsigned => arithmetic(a % b),
+        Percent => arithmetic(sa.wrapping_rem(sb) as u64),
+        Plus => arithmetic(a.wrapping_add(b)),
+        Minus => arithmetic(a.wrapping_sub(b)),
+        // Shifts have the type of their left operand.
+        LessLess => Value {
+            bits: a.checked_shl(b as u32).filter(|_| b < 64).unwrap_or(0),
+            ..lhs
+        },
+        GreaterGreater if lhs.unsigned => Value {
+            bits: a.checked_shr(b as u32).filter(|_| b < 64).unwrap_or(0),
+            ..lhs
+        },
+        GreaterGreater => Value::int(sa >> b.min(63)),
+        Less => compare(|o| o.is_lt()),
+        Greater => compare(|o| o.is_gt()),
+        LessEqual => compare(|o| o.is_le()),
+        GreaterEqual => compare(|o| o.is_ge()),
+        EqualEqual => Value::int((a == b) as i64),
+        BangEqual => Value::int((a != b) as i64),
+        Amp => arithmetic(a & b),
+        Caret => arithmetic(a ^ b),
+        Pipe => arithmetic(a | b),
+        AmpAmp => Value::int((lhs.is_true() && rhs.is_true()) as i64),
+        PipePipe => Value::int((lhs.is_true() || rhs.is_true()) as i64),
+        _ => unreachable!(),
+    })
+}
+
+/// Returns the value of a character constant in an `#if` expression,
+/// where plain `char` is signed and other character types promote to
+/// `uintmax_t`, except `wchar_t` where it is `int`.
+fn char_value(spelling: &str, dialect: Dialect, model: DataModel) -> Option<Value> {
+    let literal = decode_literal(spelling, dialect, model).ok()?;
+    match literal.encoding {
+        Encoding::Plain => {
+            let value = match literal.units.as_slice() {
+                [unit] => *unit as u8 as i8 as i64,
+                units => units
+                    .iter()
+                    .fold(0i32, |acc, &unit| acc.wrapping_shl(8) | (unit as u8 as i32))
+                    as i64,
+            };
+            Some(Value::int(value))
+        }
+        Encoding::Wide if model.wchar_width() == 32 => {
+            Some(Value::int(*literal.units.last()? as i32 as i64))
+        }
+        _ => Some(Value {
+            bits: *literal.units.last()? as u64,
+            unsigned: true,
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn preprocess_with(preprocessor: Preprocessor) -> (Vec<String>, Vec<PpErrorKind>) {
+        let mut preprocessor = preprocessor;
+        let spellings = preprocessor.by_ref().map(|token| token.spelling).collect();
+        let errors = preprocessor
+            .take_errors()
+            .into_iter()
+            .map(|e| e.kind)
+            .collect();
+        (spellings, errors)
+    }
+
+    fn preprocess(src: &str) -> (Vec<String>, Vec<PpErrorKind>) {
+        let source = SourceFile::new(PathBuf::from("main.c"), src.to_string());
+        preprocess_with(Preprocessor::new(source))
+    }
+
+    fn spellings(src: &str) -> String {
+        let (spellings, errors) = preprocess(src);
+        assert_eq!(errors, vec![]);
+        spellings.join(" ")
+    }
+
+    #[test]
+    fn test_object_like_macros() {
+        let src = "#define x x + 1\n#define y x * 2\n#define z\ny z;\n#undef y\ny";
+        assert_eq!(spellings(src), "x + 1 * 2 ; y");
+    }
+
+    #[test]
+    fn test_function_like_macros() {
+        let src = "#define f(a, b) b - a\n#define g (x)\n#define h() 0\nf(1, (2, 3)) f g h() h";
+        assert_eq!(spellings(src), "( 2 , 3 ) - 1 f ( x ) 0 h");
+
+        let src = "#define f(x) x + 1\nf(f(1))\nf\n(2)";
+        assert_eq!(spellings(src), "1 + 1 + 1 2 + 1");
+
+        // Looking for the `(` neither executes directives nor leaves files.
+        let src = "#define F(x) x\nF\n#undef F\n(2)";
+        assert_eq!(spellings(src), "F ( 2 )");
+
+        let src = "#define p(fmt, ...) printf(fmt, __VA_ARGS__)\np(\"%d %d\", 1, 2) p(\"\")";
+        assert_eq!(
+            spellings(src),
+            "printf ( \"%d %d\" , 1 , 2 ) printf ( \"\" , )"
+        );
+    }
+
+    #[test]
+    fn test_stringify_and_paste() {
+        // Section 6.10.3.5, example 3, in part
+        let src = "#define str(s) # s\n\
+                   #define xstr(s) str(s)\n\
+                   #define glue(a, b) a ## b\n\
+                   #define INCFILE(n) vers ## n\n\
+                   #define HIGHLOW \"hello\"\n\
+                   #define LOW LOW \", world\"\n\
+                   str(  a  +  \"\\n\" 'b'  ) xstr(INCFILE(2).h)\n\
+                   glue(HIGH, LOW); glue(,) glue(x,) glue(<, :)";
+        assert_eq!(
+            spellings(src),
+            r#""a + \"\\n\" 'b'" "vers2.h" "hello" ; x <:"#
+        );
+
+        let src = "#define hash_hash # ## #\n\
+                   #define mkstr(a) # a\n\
+                   #define in_between(a) mkstr(a)\n\
+                   #define join(c, d) in_between(c hash_hash d)\n\
+                   join(x, y)";
+        assert_eq!(spellings(src), r#""x ## y""#);
+    }
+
+    #[test]
+    fn test_conditionals() {
+        let src = "#if defined(A) || 1 + 2 * 3 == 7\na\n#elif 1\nb\n#else\nc\n#endif\n\
+                   #ifdef A\nd\n#elif !defined A && (0x10 >> 4) == 1\ne\n#endif\n\
+                   #ifndef A\n#if 0\n#if 1\nf\n#else\n#error nested\n#endif\n#endif\ng\n#endif\n\
+                   #define B 2\n#if B * 'A' == 130 && -1 < 0 && !(-1 < 0u) && (1 ? 2 : 1 / 0)\nh\n#endif";
+        assert_eq!(spellings(src), "a e g h");
+    }
+
+    #[test]
+    fn test_predefined_macros() {
+        let src = "__LINE__ __FILE__ __STDC__ __STDC_VERSION__\n\
+                   #line 100 \"gram.y\"\n\
+                   #define LINE __LINE__\n\
+                   LINE __FILE__";
+        assert_eq!(spellings(src), r#"1 "main.c" 1 199901L 101 "gram.y""#);
+
+        let source = SourceFile::new(PathBuf::from("main.c"), "__STDC_VERSION__".to_string());
+        let preprocessor = Preprocessor::new(source).with_dialect(Dialect::C89);
+        assert_eq!(preprocess_with(preprocessor).0, vec!["__STDC_VERSION__"]);
+
+        assert_eq!(
+            date_time(UNIX_EPOCH + std::time::Duration::from_secs(951_827_696)),
+            ("Feb 29 2000".to_string(), "12:34:56".to_string())
+        );
+    }
+
+    #[test]
+    fn test_line_directives() {
+        // Diagnostics are located where `__LINE__` says, ignoring `#line`
+        // in skipped groups and expanding macros in `#line`.
+        let src = "#if 0\n#line 100\n#endif\nint y = __LINE__ @;\n\
+                   #define L 500\n#line L \"gen.y\"\nint z = __LINE__ @;";
+        let source = SourceFile::new(PathBuf::from("b.c"), src.to_string());
+        let mut lines = Vec::new();
+        let mut positions = Vec::new();
+        for token in Preprocessor::new(source) {
+            let location = &token.location;
+            match token.kind {
+                Const(ConstKind::Integer) => lines.push(token.spelling.clone()),
+                Error(_) => {
+                    positions.push(location.file.position(location.char_range.start, false))
+                }
+                _ => (),
+            }
+        }
+        assert_eq!(lines, ["4", "500"]);
+        assert_eq!(positions, ["b.c:4:18", "gen.y:500:18"]);
+    }
+
+    #[test]
+    fn test_includes() {
+        let dir = std::env::temp_dir().join(format!("clex-include-{}", std::process::id()));
+        fs::create_dir_all(dir.join("inc")).unwrap();
+        fs::write(dir.join("a.h"), "#pragma once\nint a;\n").unwrap();
+        fs::write(dir.join("inc/b.h"), "#define B __FILE__\n").unwrap();
+        fs::write(dir.join("inc/c.h"), "c\n#if 1\n").unwrap();
+        let src = "#include \"a.h\"\n#include \"a.h\"\n#define HEADER <b.h>\n#include HEADER\nB\n\
+                   #include <c.h>\n#include <a.h>\n#include\n";
+        let source = SourceFile::new(dir.join("main.c"), src.to_string());
+        let preprocessor = Preprocessor::new(source).with_include_path(dir.join("inc"));
+        let (spellings, errors) = preprocess_with(preprocessor);
+        fs::remove_dir_all(&dir).unwrap();
+
+        // `__FILE__` names the file where `B` is expanded.
+        let main = quote(&dir.join("main.c").display().to_string());
+        assert_eq!(spellings, vec!["int", "a", ";", &main, "c"]);
+        assert_eq!(
+            errors,
+            vec![
+                PpErrorKind::UnterminatedConditional,
+                PpErrorKind::IncludeNotFound("a.h".to_string()),
+                PpErrorKind::InvalidInclude,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_macro_name_ending_header() {
+        let dir = std::env::temp_dir().join(format!("clex-call-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("f.h"), "#define F(x) x\nF").unwrap();
+        let source = SourceFile::new(dir.join("main.c"), "#include \"f.h\"\n(1)".to_string());
+        let (spellings, errors) = preprocess_with(Preprocessor::new(source));
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(spellings.join(" "), "F ( 1 )");
+        assert_eq!(errors, vec![]);
+    }
+
+    #[test]
+    fn test_expansion_locations() {
+        let src = "#define ONE 1\n#define TWO ONE + ONE\nint x = TWO;";
+        let source = SourceFile::new(PathBuf::from("main.c"), src.to_string());
+        let tokens: Vec<PpToken> = Preprocessor::new(source).collect();
+        assert_debug_snapshot!(
+            &tokens[3..6],
+            r#"
+[
+    [Const(Integer): "1"@main.c@12..13 in ONE in TWO],
+    [Punct(Plus): "+"@main.c@30..31 in TWO],
+    [Const(Integer): "1"@main.c@12..13 in ONE in TWO],
+]"#
+        );
+        let location = &tokens[5].location;
+        let expansions: Vec<_> = location
+            .expansions()
+            .map(|e| e.call_site.byte_range.clone())
+            .collect();
+        assert_eq!(expansions, vec![32..35, 44..47]);
+        assert_eq!(location.expansion_root().line_column(), (2, 8));
+    }
+
+    #[test]
+    fn test_converted_tokens() {
+        let src = "#define T unsigned int\nT x = 1.2.3 + 1e999 @";
+        let source = SourceFile::new(PathBuf::from("main.c"), src.to_string());
+        let mut preprocessor = Preprocessor::new(source);
+        let kinds: Vec<TokenKind> = preprocessor.by_ref().map(|token| token.kind).collect();
+        assert_debug_snapshot!(
+            kinds,
+            r#"
+[
+    Keyword(
+        Unsigned,
+    ),
+    Keyword(
+        Int,
+    ),
+    Ident,
+    Punct(
+        Equal,
+    ),
+    Error(
+        InvalidNumber,
+    ),
+    Punct(
+        Plus,
+    ),
+    Const(
+        Float,
+    ),
+    Error(
+        UnknownPunctuator,
+    ),
+]"#
+        );
+        let errors: Vec<PpErrorKind> = preprocessor
+            .take_errors()
+            .into_iter()
+            .map(|e| e.kind)
+            .collect();
+        let codes: Vec<_> = errors.iter().map(|kind| kind.code()).collect();
+        assert_eq!(codes, [Some(FloatOverflow.code())]);
+
+        // Warnings point into the token, past line splices.
+        let src = "#define S \"a\\\n\\q\"\nchar *s = \"\\q\"; S";
+        let source = SourceFile::new(PathBuf::from("main.c"), src.to_string());
+        let mut preprocessor = Preprocessor::new(source);
+        preprocessor.by_ref().for_each(drop);
+        let ranges: Vec<_> = preprocessor
+            .take_errors()
+            .iter()
+            .map(|e| e.diagnostic().primary.byte_range)
+            .collect();
+        assert_eq!(ranges, [29..31, 14..16]);
+    }
+
+    #[test]
+    fn test_preprocessing_errors() {
+        let src = "#define f(x) #y\n#define g(x, x)\n#define h ## x\n#define 1\n\
+                   #define A 1\n#define A 2\n#define m(a, b) a\nm(1) m(1\n";
+        let (_, errors) = preprocess(src);
+        assert_eq!(
+            errors,
+            vec![
+                PpErrorKind::InvalidStringify,
+                PpErrorKind::InvalidMacroParameters,
+                PpErrorKind::PasteAtEdge,
+                PpErrorKind::InvalidMacroName,
+                PpErrorKind::MacroRedefined("A".to_string()),
+                PpErrorKind::WrongArgumentCount {
+                    name: "m".to_string(),
+                    expected: 2,
+                    found: 1
+                },
+                PpErrorKind::UnterminatedMacroCall("m".to_string()),
+            ]
+        );
+
+        let src = "#if 1 / 0\n#elif\n#endif\n#else\n#if 0\n#else\n#else\n#endif\n\
+                   #line x\n#foo\n#define cat(a, b) a ## b\ncat(., .)\n#error stop  here\n#if 1";
+        let (spellings, errors) = preprocess(src);
+        assert_eq!(spellings, vec![".", "."]);
+        assert_eq!(
+            errors,
+            vec![
+                PpErrorKind::DivisionByZero,
+                PpErrorKind::InvalidExpression,
+                PpErrorKind::UnmatchedConditional("else".to_string()),
+                PpErrorKind::ConditionalAfterElse("else".to_string()),
+                PpErrorKind::InvalidLineDirective,
+                PpErrorKind::UnknownDirective("foo".to_string()),
+                PpErrorKind::InvalidPaste("..".to_string()),
+                PpErrorKind::ErrorDirective("stop here".to_string()),
+                PpErrorKind::UnterminatedConditional,
+            ]
+        );
+    }
+}
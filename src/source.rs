@@ -1,14 +1,13 @@
 //! This module contains source-file-related functionality.
 
-use crate::token::*;
 use anyhow::Result;
+use std::cell::RefCell;
 use std::fs::File;
 use std::io::prelude::*;
 use std::path::PathBuf;
 use std::rc::Rc;
-use termcolor::{Color::Green, ColorChoice, ColorSpec, StandardStream, WriteColor};
-use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
 
+/// A source file loaded into memory, with its line breaks indexed.
 #[derive(Clone)]
 pub struct SourceFile {
     /// The path of the file
@@ -17,27 +16,57 @@ pub struct SourceFile {
     pub src: Rc<String>,
     /// Locations of line beginnings
     pub lines: Rc<Vec<usize>>,
+    /// The `#line` directives executed so far, in source order
+    line_markers: Rc<RefCell<Vec<LineMarker>>>,
+}
+
+/// The effect of a `#line` directive (Section 6.10.4).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LineMarker {
+    /// The zero-based index of the line after the directive
+    pub line: usize,
+    /// The line number the directive gives to that line
+    pub presumed_line: usize,
+    /// The file name given by the directive or an earlier one, if any
+    pub path: Option<String>,
+}
+
+/// A location as set by `#line` directives, which is how compilers report
+/// locations in generated code.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PresumedLocation {
+    pub path: String,
+    /// Zero-based, like the lines of [`SourceFile::lookup_line_column`]
+    pub line: usize,
+    pub column: usize,
 }
 
 impl SourceFile {
+    /// Reads the file at `path`.
     pub fn open(path: PathBuf) -> Result<Self> {
         let mut buf = String::new();
         File::open(&path)?.read_to_string(&mut buf)?;
+        Ok(Self::new(path, buf))
+    }
 
+    /// Creates a source file from in-memory source code; `path` is only
+    /// used for reporting.
+    pub fn new(path: PathBuf, mut buf: String) -> Self {
         // Remove UTF-8 BOM, if any.
-        if buf.starts_with("\u{feff}") {
+        if buf.starts_with('\u{feff}') {
             buf.drain(..3);
         }
         // Append \n if necessary.
-        if !buf.ends_with("\n") {
-            buf.push_str("\n");
+        if !buf.ends_with('\n') {
+            buf.push('\n');
         }
         let lines = Self::analyze_lines(buf.as_str());
-        Ok(SourceFile {
-            path: path,
+        SourceFile {
+            path,
             src: Rc::new(buf),
             lines: Rc::new(lines),
-        })
+            line_markers: Rc::default(),
+        }
     }
 
     /// Find out all line breaks.
@@ -53,50 +82,102 @@ impl SourceFile {
         lines
     }
 
+    /// Records the effect of a `#line` directive, as found by whoever
+    /// executes the directives of the file. A marker without a file name
+    /// keeps the one of the marker before it, if any.
+    pub fn add_line_marker(&self, mut marker: LineMarker) {
+        let mut markers = self.line_markers.borrow_mut();
+        let index = markers.partition_point(|m| m.line < marker.line);
+        if marker.path.is_none() {
+            let previous = index.checked_sub(1).map(|i| &markers[i]);
+            marker.path = previous.and_then(|m| m.path.clone());
+        }
+        match markers.get_mut(index) {
+            Some(m) if m.line == marker.line => *m = marker,
+            _ => markers.insert(index, marker),
+        }
+    }
+
     /// Returns the line and column position corresponding to the given `char_pos`.
     pub fn lookup_line_column(&self, char_pos: usize) -> (usize, usize) {
         let line = self
             .lines
             .binary_search(&char_pos)
-            .map_or_else(|e| e - 1, |t| t);
+            .unwrap_or_else(|e| e - 1);
         let column = char_pos - self.lines[line];
         (line, column)
     }
 
+    /// Returns the location of `char_pos` as set by the last recorded
+    /// `#line` directive before it, if any.
+    pub fn lookup_presumed(&self, char_pos: usize) -> PresumedLocation {
+        let (line, column) = self.lookup_line_column(char_pos);
+        let markers = self.line_markers.borrow();
+        let index = markers.partition_point(|marker| marker.line <= line);
+        let (line, path) = match index.checked_sub(1).map(|i| &markers[i]) {
+            Some(marker) => (
+                marker.presumed_line - 1 + (line - marker.line),
+                marker.path.as_deref(),
+            ),
+            None => (line, None),
+        };
+        let path = match path {
+            Some(path) => path.to_string(),
+            None => self.path.display().to_string(),
+        };
+        PresumedLocation { path, line, column }
+    }
+
+    /// Returns `path:line:column` for `char_pos`, as set by `#line`
+    /// directives unless `physical`.
+    pub fn position(&self, char_pos: usize, physical: bool) -> String {
+        let (path, line, column) = match physical {
+            true => {
+                let (line, column) = self.lookup_line_column(char_pos);
+                (self.path.display().to_string(), line, column)
+            }
+            false => {
+                let presumed = self.lookup_presumed(char_pos);
+                (presumed.path, presumed.line, presumed.column)
+            }
+        };
+        format!("{}:{}:{}", path, line + 1, column + 1)
+    }
+
+    /// Returns the content of the `line`-th line, without the line break.
     pub fn get_line(&self, line: usize) -> String {
         let start = self.lines[line];
         let end = self.lines[line + 1];
         self.src.chars().skip(start).take(end - start - 1).collect()
     }
+}
 
-    pub fn display_error_hint(&self, token: &Token) -> Result<()> {
-        if let Error(error_kind) = token.kind {
-            let mut stderr = StandardStream::stderr(ColorChoice::Auto);
-
-            let (line, column) = self.lookup_line_column(token.char_range.start);
-            let line_src = self.get_line(line);
-            writeln!(&mut stderr, "{}", line_src)?;
-
-            let leading_spaces = line_src.chars().take(column).fold(0, |acc, c| {
-                acc + UnicodeWidthChar::width(c).unwrap_or_default()
-            });
-            write!(&mut stderr, "{: <1$}", "", leading_spaces)?;
-
-            let token_display_width =
-                UnicodeWidthStr::width(&self.src.as_str()[token.byte_range.clone()]);
-
-            stderr.set_color(ColorSpec::new().set_fg(Some(Green)).set_bold(true))?;
-            writeln!(
-                &mut stderr,
-                "{} {:?}",
-                std::iter::repeat('^')
-                    .take(token_display_width)
-                    .collect::<String>(),
-                error_kind
-            )?;
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-            stderr.reset()?;
-        }
-        Ok(())
+    #[test]
+    fn test_presumed_locations() {
+        let src = "A\n#line 10\nB\n#line 20 \"gram.y\"\nC\nD\n#line 5\nE\nF";
+        let source = SourceFile::new(PathBuf::from("gen.c"), src.to_string());
+        let marker = |line, presumed_line, path: Option<&str>| LineMarker {
+            line,
+            presumed_line,
+            path: path.map(String::from),
+        };
+        source.add_line_marker(marker(4, 20, Some("gram.y")));
+        source.add_line_marker(marker(2, 10, None));
+        source.add_line_marker(marker(7, 5, None));
+        let presumed = |c: char| {
+            let location = source.lookup_presumed(src.find(c).unwrap());
+            (location.path, location.line + 1)
+        };
+        assert_eq!(presumed('A'), ("gen.c".to_string(), 1));
+        assert_eq!(presumed('B'), ("gen.c".to_string(), 10));
+        assert_eq!(presumed('C'), ("gram.y".to_string(), 20));
+        assert_eq!(presumed('D'), ("gram.y".to_string(), 21));
+        assert_eq!(presumed('E'), ("gram.y".to_string(), 5));
+        assert_eq!(presumed('F'), ("gram.y".to_string(), 6));
+        assert_eq!(source.position(src.find('F').unwrap(), true), "gen.c:9:1");
     }
 }
@@ -1,22 +1,41 @@
+//! Statistics over a token stream.
+
+use crate::locale;
 use crate::source::SourceFile;
 use crate::token::*;
+use std::collections::BTreeMap;
 use std::fmt;
 
+/// Counts of lines, chars and each kind of token in a source file.
 #[derive(Debug, Default)]
 pub struct Statistics {
+    /// Number of lines
     pub lines: usize,
+    /// Number of chars
     pub len: usize,
     pub keywords: usize,
+    /// Keywords broken down by [`KeywordKind`]
+    pub keyword_counts: BTreeMap<KeywordKind, usize>,
     pub idents: usize,
     pub floats: usize,
     pub ints: usize,
     pub chars: usize,
+    /// Char constants broken down by [`Encoding`]
+    pub char_encodings: BTreeMap<Encoding, usize>,
     pub strs: usize,
+    /// String literals broken down by [`Encoding`]
+    pub str_encodings: BTreeMap<Encoding, usize>,
     pub puncts: usize,
+    /// Punctuators broken down by [`PunctClass`]
+    pub punct_classes: BTreeMap<PunctClass, usize>,
+    pub directives: usize,
+    /// Preprocessing directives broken down by [`DirectiveKind`]
+    pub directive_counts: BTreeMap<DirectiveKind, usize>,
     pub errors: usize,
 }
 
 impl Statistics {
+    /// Creates empty token counts for `source`.
     pub fn new(source: &SourceFile) -> Self {
         let lines = source.lines.len();
         let len = source.src.chars().count();
@@ -27,15 +46,36 @@ impl Statistics {
         }
     }
 
+    /// Counts `token` in. Only errors count within directives, whose
+    /// contents are not part of the program as such.
     pub fn track(&mut self, token: &Token) {
+        if token.in_directive && !matches!(token.kind, Error(_)) {
+            return;
+        }
         match token.kind {
-            Keyword => self.keywords += 1,
+            Keyword(kind) => {
+                self.keywords += 1;
+                *self.keyword_counts.entry(kind).or_default() += 1;
+            }
             Ident => self.idents += 1,
             Const(Float) => self.floats += 1,
             Const(Integer) => self.ints += 1,
-            Const(Char) => self.chars += 1,
-            StrLit => self.strs += 1,
-            Punct => self.puncts += 1,
+            Const(Char(encoding)) => {
+                self.chars += 1;
+                *self.char_encodings.entry(encoding).or_default() += 1;
+            }
+            StrLit(encoding) => {
+                self.strs += 1;
+                *self.str_encodings.entry(encoding).or_default() += 1;
+            }
+            Punct(kind) => {
+                self.puncts += 1;
+                *self.punct_classes.entry(kind.class()).or_default() += 1;
+            }
+            Directive(kind) => {
+                self.directives += 1;
+                *self.directive_counts.entry(kind).or_default() += 1;
+            }
             Error(_) => self.errors += 1,
             _ => (),
         }
@@ -43,17 +83,40 @@ impl Statistics {
 }
 
 impl fmt::Display for Statistics {
+    /// Writes the statistics, labelled in the current
+    /// [`Catalog`](crate::Catalog).
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        writeln!(f, "   Total lines: {}", self.lines)?;
-        writeln!(f, "   Total chars: {}", self.len)?;
-        writeln!(f, "   Keywords: {}", self.keywords)?;
-        writeln!(f, "   Identifiers: {}", self.idents)?;
-        writeln!(f, "   Floating constants: {}", self.floats)?;
-        writeln!(f, "   Integer constants: {}", self.ints)?;
-        writeln!(f, "   Char constants: {}", self.chars)?;
-        writeln!(f, "   String literals: {}", self.strs)?;
-        writeln!(f, "   Punctuators: {}", self.puncts)?;
-        writeln!(f, "   Errors: {}", self.errors)?;
+        let label = |key: &str| locale::message(&format!("stats.{}", key), &[]);
+        writeln!(f, "   {}: {}", label("lines"), self.lines)?;
+        writeln!(f, "   {}: {}", label("len"), self.len)?;
+        writeln!(f, "   {}: {}", label("keywords"), self.keywords)?;
+        for (kind, count) in &self.keyword_counts {
+            writeln!(f, "      {}: {}", kind.as_str(), count)?;
+        }
+        writeln!(f, "   {}: {}", label("idents"), self.idents)?;
+        writeln!(f, "   {}: {}", label("floats"), self.floats)?;
+        writeln!(f, "   {}: {}", label("ints"), self.ints)?;
+        writeln!(f, "   {}: {}", label("chars"), self.chars)?;
+        for (encoding, count) in &self.char_encodings {
+            let name = locale::message(&format!("encoding.{:?}", encoding), &[]);
+            writeln!(f, "      {}: {}", name, count)?;
+        }
+        writeln!(f, "   {}: {}", label("strs"), self.strs)?;
+        for (encoding, count) in &self.str_encodings {
+            let name = locale::message(&format!("encoding.{:?}", encoding), &[]);
+            writeln!(f, "      {}: {}", name, count)?;
+        }
+        writeln!(f, "   {}: {}", label("puncts"), self.puncts)?;
+        for (class, count) in &self.punct_classes {
+            let name = locale::message(&format!("class.{:?}", class), &[]);
+            writeln!(f, "      {}: {}", name, count)?;
+        }
+        writeln!(f, "   {}: {}", label("directives"), self.directives)?;
+        for (kind, count) in &self.directive_counts {
+            let name = locale::message(&format!("directive.{:?}", kind), &[]);
+            writeln!(f, "      {}: {}", name, count)?;
+        }
+        writeln!(f, "   {}: {}", label("errors"), self.errors)?;
         Ok(())
     }
 }
@@ -1,30 +1,414 @@
+//! Tokens produced by the lexer.
+
+use crate::dialect::Dialect;
+use crate::lexer::{logical_spelling, source_ranges};
+use crate::locale;
+use std::borrow::Cow;
 use std::fmt;
 use std::ops::Range;
+use std::str::FromStr;
 pub use ConstKind::*;
 pub use ErrorKind::*;
 pub use TokenKind::*;
+pub use WarningKind::*;
 
+/// The category of a token.
+///
+/// Whitespace, comments and lexical errors are represented as tokens too,
+/// so that the lexer never stops halfway; [`Lexer::iter`] filters out the
+/// trivia for you.
+///
+/// [`Lexer::iter`]: crate::Lexer::iter
 #[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
 pub enum TokenKind {
     /* tokens */
-    Keyword,
+    Keyword(KeywordKind),
     Ident,
     Const(ConstKind),
-    StrLit,
-    Punct,
+    StrLit(Encoding),
+    Punct(PunctKind),
+    /// The head of a preprocessing directive, from the `#` at the start of
+    /// a line to the directive name, as lexed in [`LexMode::Normal`]
+    ///
+    /// [`LexMode::Normal`]: crate::LexMode::Normal
+    Directive(DirectiveKind),
+    /* preprocessing tokens */
+    PpNumber,
+    HeaderName,
+    /// A non-whitespace char that is part of no other preprocessing token
+    Other,
     /* compiler internal tokens */
     Comment,
     Whitespace,
+    /// Backslashes followed by new-lines between two tokens
+    LineSplice,
     Error(ErrorKind),
 }
 
+impl TokenKind {
+    /// Returns `true` for whitespace, comments and line splices.
+    pub fn is_trivia(self) -> bool {
+        matches!(self, Whitespace | Comment | LineSplice)
+    }
+}
+
+macro_rules! keywords {
+    ($($kind:ident => $spelling:literal since $dialect:ident,)*) => {
+        /// The keywords of Section 6.4.1, in every revision of the standard.
+        #[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+        pub enum KeywordKind {
+            $($kind,)*
+        }
+
+        impl KeywordKind {
+            /// All keywords, in declaration order.
+            pub const ALL: &'static [KeywordKind] = &[$(KeywordKind::$kind,)*];
+
+            /// Returns the spelling of the keyword.
+            pub fn as_str(self) -> &'static str {
+                match self {
+                    $(KeywordKind::$kind => $spelling,)*
+                }
+            }
+
+            /// Returns the first revision of the standard in which the
+            /// spelling is a keyword rather than an identifier.
+            pub fn since(self) -> Dialect {
+                match self {
+                    $(KeywordKind::$kind => Dialect::$dialect,)*
+                }
+            }
+        }
+
+        impl FromStr for KeywordKind {
+            type Err = ();
+
+            /// Looks up a keyword by spelling, regardless of the dialect.
+            fn from_str(s: &str) -> Result<Self, Self::Err> {
+                match s {
+                    $($spelling => Ok(KeywordKind::$kind),)*
+                    _ => Err(()),
+                }
+            }
+        }
+    };
+}
+
+// Section 6.4.1 Keywords
+#[rustfmt::skip]
+keywords! {
+    Auto                   => "auto"           since C89,
+    Break                  => "break"          since C89,
+    Case                   => "case"           since C89,
+    Char                   => "char"           since C89,
+    Const                  => "const"          since C89,
+    Continue               => "continue"       since C89,
+    Default                => "default"        since C89,
+    Do                     => "do"             since C89,
+    Double                 => "double"         since C89,
+    Else                   => "else"           since C89,
+    Enum                   => "enum"           since C89,
+    Extern                 => "extern"         since C89,
+    Float                  => "float"          since C89,
+    For                    => "for"            since C89,
+    Goto                   => "goto"           since C89,
+    If                     => "if"             since C89,
+    Int                    => "int"            since C89,
+    Long                   => "long"           since C89,
+    Register               => "register"       since C89,
+    Return                 => "return"         since C89,
+    Short                  => "short"          since C89,
+    Signed                 => "signed"         since C89,
+    Sizeof                 => "sizeof"         since C89,
+    Static                 => "static"         since C89,
+    Struct                 => "struct"         since C89,
+    Switch                 => "switch"         since C89,
+    Typedef                => "typedef"        since C89,
+    Union                  => "union"          since C89,
+    Unsigned               => "unsigned"       since C89,
+    Void                   => "void"           since C89,
+    Volatile               => "volatile"       since C89,
+    While                  => "while"          since C89,
+    Inline                 => "inline"         since C99,
+    Restrict               => "restrict"       since C99,
+    UnderscoreBool         => "_Bool"          since C99,
+    UnderscoreComplex      => "_Complex"       since C99,
+    UnderscoreImaginary    => "_Imaginary"     since C99,
+    UnderscoreAlignas      => "_Alignas"       since C11,
+    UnderscoreAlignof      => "_Alignof"       since C11,
+    UnderscoreAtomic       => "_Atomic"        since C11,
+    UnderscoreGeneric      => "_Generic"       since C11,
+    UnderscoreNoreturn     => "_Noreturn"      since C11,
+    UnderscoreStaticAssert => "_Static_assert" since C11,
+    UnderscoreThreadLocal  => "_Thread_local"  since C11,
+    Alignas                => "alignas"        since C23,
+    Alignof                => "alignof"        since C23,
+    Bool                   => "bool"           since C23,
+    Constexpr              => "constexpr"      since C23,
+    False                  => "false"          since C23,
+    Nullptr                => "nullptr"        since C23,
+    StaticAssert           => "static_assert"  since C23,
+    ThreadLocal            => "thread_local"   since C23,
+    True                   => "true"           since C23,
+    Typeof                 => "typeof"         since C23,
+    TypeofUnqual           => "typeof_unqual"  since C23,
+    UnderscoreBitInt       => "_BitInt"        since C23,
+    UnderscoreDecimal128   => "_Decimal128"    since C23,
+    UnderscoreDecimal32    => "_Decimal32"     since C23,
+    UnderscoreDecimal64    => "_Decimal64"     since C23,
+}
+
+/// The punctuators of Section 6.4.6.
+///
+/// The digraphs `<:`, `:>`, `<%`, `%>`, `%:` and `%:%:` behave the same as
+/// `[`, `]`, `{`, `}`, `#` and `##` respectively, and thus share their kinds.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum PunctKind {
+    LBracket,
+    RBracket,
+    LParen,
+    RParen,
+    LBrace,
+    RBrace,
+    Dot,
+    Arrow,
+    PlusPlus,
+    MinusMinus,
+    Amp,
+    Star,
+    Plus,
+    Minus,
+    Tilde,
+    Bang,
+    Slash,
+    Percent,
+    LessLess,
+    GreaterGreater,
+    Less,
+    Greater,
+    LessEqual,
+    GreaterEqual,
+    EqualEqual,
+    BangEqual,
+    Caret,
+    Pipe,
+    AmpAmp,
+    PipePipe,
+    Question,
+    Colon,
+    /// `::`, since C23
+    ColonColon,
+    Semi,
+    Ellipsis,
+    Equal,
+    StarEqual,
+    SlashEqual,
+    PercentEqual,
+    PlusEqual,
+    MinusEqual,
+    LessLessEqual,
+    GreaterGreaterEqual,
+    AmpEqual,
+    CaretEqual,
+    PipeEqual,
+    Comma,
+    Hash,
+    HashHash,
+}
+
+/// A coarse classification of punctuators by the operators they denote.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum PunctClass {
+    /// `+ - * / % ++ --`
+    Arithmetic,
+    /// `& | ^ ~ << >>`
+    Bitwise,
+    /// `&& || !`
+    Logical,
+    /// `< > <= >= == !=`
+    Relational,
+    /// `=` and the compound assignments
+    Assignment,
+    /// `. ->`
+    Member,
+    /// `( ) [ ] { }`
+    Bracket,
+    /// `, ; : :: ? ...`
+    Separator,
+    /// `# ##`
+    Preprocessor,
+}
+
+impl PunctKind {
+    /// Returns the canonical spelling of the punctuator.
+    pub fn as_str(self) -> &'static str {
+        use PunctKind::*;
+        match self {
+            LBracket => "[",
+            RBracket => "]",
+            LParen => "(",
+            RParen => ")",
+            LBrace => "{",
+            RBrace => "}",
+            Dot => ".",
+            Arrow => "->",
+            PlusPlus => "++",
+            MinusMinus => "--",
+            Amp => "&",
+            Star => "*",
+            Plus => "+",
+            Minus => "-",
+            Tilde => "~",
+            Bang => "!",
+            Slash => "/",
+            Percent => "%",
+            LessLess => "<<",
+            GreaterGreater => ">>",
+            Less => "<",
+            Greater => ">",
+            LessEqual => "<=",
+            GreaterEqual => ">=",
+            EqualEqual => "==",
+            BangEqual => "!=",
+            Caret => "^",
+            Pipe => "|",
+            AmpAmp => "&&",
+            PipePipe => "||",
+            Question => "?",
+            Colon => ":",
+            ColonColon => "::",
+            Semi => ";",
+            Ellipsis => "...",
+            Equal => "=",
+            StarEqual => "*=",
+            SlashEqual => "/=",
+            PercentEqual => "%=",
+            PlusEqual => "+=",
+            MinusEqual => "-=",
+            LessLessEqual => "<<=",
+            GreaterGreaterEqual => ">>=",
+            AmpEqual => "&=",
+            CaretEqual => "^=",
+            PipeEqual => "|=",
+            Comma => ",",
+            Hash => "#",
+            HashHash => "##",
+        }
+    }
+
+    /// Returns the class of operators the punctuator belongs to.
+    pub fn class(self) -> PunctClass {
+        use PunctKind::*;
+        match self {
+            Plus | Minus | Star | Slash | Percent | PlusPlus | MinusMinus => PunctClass::Arithmetic,
+            Amp | Pipe | Caret | Tilde | LessLess | GreaterGreater => PunctClass::Bitwise,
+            AmpAmp | PipePipe | Bang => PunctClass::Logical,
+            Less | Greater | LessEqual | GreaterEqual | EqualEqual | BangEqual => {
+                PunctClass::Relational
+            }
+            Equal | StarEqual | SlashEqual | PercentEqual | PlusEqual | MinusEqual
+            | LessLessEqual | GreaterGreaterEqual | AmpEqual | CaretEqual | PipeEqual => {
+                PunctClass::Assignment
+            }
+            Dot | Arrow => PunctClass::Member,
+            LParen | RParen | LBracket | RBracket | LBrace | RBrace => PunctClass::Bracket,
+            Comma | Semi | Colon | ColonColon | Question | Ellipsis => PunctClass::Separator,
+            Hash | HashHash => PunctClass::Preprocessor,
+        }
+    }
+}
+
+/// The kind of a constant (Section 6.4.4).
 #[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
 pub enum ConstKind {
     Float,
     Integer,
-    Char,
+    Char(Encoding),
+}
+
+/// The encoding prefix of a character constant or string literal
+/// (Sections 6.4.4.4 and 6.4.5).
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Encoding {
+    /// No prefix
+    Plain,
+    /// `u8`, since C11 for string literals and C23 for character constants
+    Utf8,
+    /// `L`
+    Wide,
+    /// `u`, since C11
+    Utf16,
+    /// `U`, since C11
+    Utf32,
+}
+
+impl Encoding {
+    /// Returns the prefix spelling the encoding.
+    pub fn prefix(self) -> &'static str {
+        match self {
+            Encoding::Plain => "",
+            Encoding::Utf8 => "u8",
+            Encoding::Wide => "L",
+            Encoding::Utf16 => "u",
+            Encoding::Utf32 => "U",
+        }
+    }
+}
+
+/// The kind of a preprocessing directive (Section 6.10).
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum DirectiveKind {
+    Include,
+    Define,
+    Undef,
+    If,
+    Ifdef,
+    Ifndef,
+    Elif,
+    /// Since C23
+    Elifdef,
+    /// Since C23
+    Elifndef,
+    Else,
+    Endif,
+    Line,
+    Error,
+    /// Since C23
+    Warning,
+    Pragma,
+    /// A `#` alone on its line
+    Null,
+    /// A `#` followed by anything else, which only makes sense in a
+    /// skipped group
+    NonDirective,
+}
+
+impl DirectiveKind {
+    /// Returns the directive named `name` after the `#`, if any in `dialect`.
+    pub fn from_name(name: &str, dialect: Dialect) -> Self {
+        use DirectiveKind::*;
+        match name {
+            "include" => Include,
+            "define" => Define,
+            "undef" => Undef,
+            "if" => If,
+            "ifdef" => Ifdef,
+            "ifndef" => Ifndef,
+            "elif" => Elif,
+            "elifdef" if dialect.has_c23_directives() => Elifdef,
+            "elifndef" if dialect.has_c23_directives() => Elifndef,
+            "else" => Else,
+            "endif" => Endif,
+            "line" => Line,
+            "error" => Error,
+            "warning" if dialect.has_c23_directives() => Warning,
+            "pragma" => Pragma,
+            _ => NonDirective,
+        }
+    }
 }
 
+/// The lexical errors the lexer is able to detect and recover from.
 #[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
 pub enum ErrorKind {
     UnclosedBlockComment,
@@ -35,10 +419,61 @@ pub enum ErrorKind {
     InvalidIntegerSuffix,
     InvalidFloatingSuffix,
     NoHexadecimalDigits,
-    #[allow(unused)]
+    MissingBinaryExponent,
+    InvalidUniversalCharacterName,
+    InvalidIdentifierCharacter,
+    EmptyHexEscape,
+    HexEscapeOutOfRange,
+    OctalEscapeOutOfRange,
+    NoBinaryDigits,
+    InvalidBinaryDigit,
+    InvalidDigitSeparator,
+    InvalidOctalDigit,
+    InvalidNumber,
+    Utf8CharacterTooLarge,
     Unknown,
 }
 
+impl ErrorKind {
+    /// Returns the stable code of the error in diagnostics.
+    pub fn code(self) -> &'static str {
+        match self {
+            UnclosedBlockComment => "E0001",
+            UnterminatedString => "E0002",
+            UnterminatedChar => "E0003",
+            UnknownPunctuator => "E0004",
+            UnexpectedCharacter => "E0005",
+            InvalidIntegerSuffix => "E0006",
+            InvalidFloatingSuffix => "E0007",
+            NoHexadecimalDigits => "E0008",
+            MissingBinaryExponent => "E0009",
+            InvalidUniversalCharacterName => "E0010",
+            InvalidIdentifierCharacter => "E0011",
+            EmptyHexEscape => "E0012",
+            HexEscapeOutOfRange => "E0013",
+            OctalEscapeOutOfRange => "E0014",
+            NoBinaryDigits => "E0015",
+            InvalidBinaryDigit => "E0016",
+            InvalidDigitSeparator => "E0017",
+            InvalidOctalDigit => "E0018",
+            InvalidNumber => "E0019",
+            Unknown => "E0020",
+            Utf8CharacterTooLarge => "E0021",
+        }
+    }
+
+    /// Returns a one-line description of the error, in the current
+    /// [`Catalog`](crate::Catalog).
+    pub fn message(self) -> String {
+        locale::message(self.code(), &[])
+    }
+
+    /// Returns the likely cause of the error.
+    pub fn cause(self) -> String {
+        locale::message(&format!("{}.cause", self.code()), &[])
+    }
+}
+
 /// Instead of storing the actual token content, `Token` stores
 /// the text range of it and maintains a reference to the source str.
 ///
@@ -49,10 +484,29 @@ pub enum ErrorKind {
 /// for human-readable text ranges.
 #[derive(Clone, PartialEq, Eq)]
 pub struct Token<'a> {
+    /// The category of the token
     pub kind: TokenKind,
+    /// Byte offsets of the token in `src`
     pub byte_range: Range<usize>,
+    /// Char offsets of the token in `src`
     pub char_range: Range<usize>,
+    /// The complete source code the token comes from
     pub src: &'a str,
+    /// Whether the token contains line splices, in which case its
+    /// [`spelling`](Token::spelling) differs from its source text
+    pub spliced: bool,
+    /// Whether the token contains trigraphs that have been replaced
+    pub has_trigraphs: bool,
+    /// Whether the token is the first one on its line, not counting
+    /// whitespace, comments and line splices
+    pub at_line_start: bool,
+    /// Whether whitespace or a comment comes right before the token
+    pub has_leading_space: bool,
+    /// Whether the token is part of a preprocessing directive, after its
+    /// [`Directive`] head, in [`LexMode::Normal`]
+    ///
+    /// [`LexMode::Normal`]: crate::LexMode::Normal
+    pub in_directive: bool,
 }
 
 impl fmt::Debug for Token<'_> {
@@ -63,12 +517,7 @@ impl fmt::Debug for Token<'_> {
 
 impl fmt::Display for Token<'_> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(
-            f,
-            "[{:?}: {:?}]",
-            self.kind,
-            &self.src[self.byte_range.clone()],
-        )
+        write!(f, "[{:?}: {:?}]", self.kind, self.as_str(),)
     }
 }
 
@@ -84,6 +533,126 @@ impl<'a> Token<'a> {
             byte_range,
             char_range,
             src,
+            spliced: false,
+            has_trigraphs: false,
+            at_line_start: false,
+            has_leading_space: false,
+            in_directive: false,
         }
     }
+
+    /// Returns the source text of the token.
+    pub fn as_str(&self) -> &'a str {
+        &self.src[self.byte_range.clone()]
+    }
+
+    /// Returns the logical spelling of the token, i.e. its source text
+    /// with trigraphs replaced and line splices removed.
+    pub fn spelling(&self) -> Cow<'a, str> {
+        match self.spliced || self.has_trigraphs {
+            true => logical_spelling(self.as_str(), self.has_trigraphs),
+            false => Cow::Borrowed(self.as_str()),
+        }
+    }
+
+    /// Maps `logical`, a byte range in the [`spelling`](Token::spelling)
+    /// of the token, back to byte and char ranges in `src`.
+    pub fn source_ranges(&self, logical: Range<usize>) -> (Range<usize>, Range<usize>) {
+        let begin = (self.byte_range.start, self.char_range.start);
+        source_ranges(self.src, self.has_trigraphs, begin.0, begin.1, logical)
+    }
+
+    /// Returns the byte and char ranges of the offending text of an error
+    /// token: the first `8` or `9` of an octal constant, or the whole token
+    /// otherwise.
+    pub fn error_range(&self) -> (Range<usize>, Range<usize>) {
+        if let Error(InvalidOctalDigit) = self.kind {
+            let text = self.as_str();
+            if let Some(byte_offset) = text.find(['8', '9']) {
+                let byte_start = self.byte_range.start + byte_offset;
+                let char_start = self.char_range.start + text[..byte_offset].chars().count();
+                return (byte_start..byte_start + 1, char_start..char_start + 1);
+            }
+        }
+        (self.byte_range.clone(), self.char_range.clone())
+    }
+
+    /// Returns the canonical spelling of a punctuator, so that digraphs
+    /// are reported as the punctuators they stand for, e.g. `[` for `<:`.
+    ///
+    /// Returns `None` if the token is not a punctuator.
+    pub fn canonical_spelling(&self) -> Option<&'static str> {
+        match self.kind {
+            Punct(kind) => Some(kind.as_str()),
+            _ => None,
+        }
+    }
+}
+
+/// A token together with the trivia around it, as produced by
+/// [`Lexer::lossless`].
+///
+/// Trailing trivia runs up to and including the end of the line the token
+/// ends on; everything else before the next token is its leading trivia.
+/// Writing out every item of the stream with [`Display`](fmt::Display)
+/// reproduces the source text byte for byte.
+///
+/// [`Lexer::lossless`]: crate::Lexer::lossless
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LosslessToken<'a> {
+    /// Whitespace, comments and line splices before the token
+    pub leading: Vec<Token<'a>>,
+    /// The token, or `None` for the end of input, whose leading trivia
+    /// is what follows the trailing trivia of the last token
+    pub token: Option<Token<'a>>,
+    /// Whitespace, comments and line splices after the token on the same line
+    pub trailing: Vec<Token<'a>>,
+}
+
+impl fmt::Display for LosslessToken<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut tokens = self.leading.iter().chain(&self.token).chain(&self.trailing);
+        tokens.try_for_each(|token| f.write_str(token.as_str()))
+    }
+}
+
+/// Problems that do not prevent the lexer from producing a valid token.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum WarningKind {
+    /// A trigraph in a string literal has been replaced
+    TrigraphConverted,
+    /// A trigraph in a string literal has been left alone, since trigraphs
+    /// are disabled
+    TrigraphIgnored,
+    /// A floating constant is too large for its type, and becomes infinity
+    FloatOverflow,
+    /// A nonzero floating constant is too small for its type, and becomes zero
+    FloatUnderflow,
+    /// An escape sequence not in Section 6.4.4.4, such as `\q`, which stands
+    /// for the char after the backslash
+    UnknownEscapeSequence,
+}
+
+impl WarningKind {
+    /// Returns the stable code of the warning in diagnostics.
+    pub fn code(self) -> &'static str {
+        match self {
+            TrigraphConverted => "W0001",
+            TrigraphIgnored => "W0002",
+            FloatOverflow => "W0003",
+            FloatUnderflow => "W0004",
+            UnknownEscapeSequence => "W0005",
+        }
+    }
+
+    /// Returns a one-line description of the warning, in the current
+    /// [`Catalog`](crate::Catalog).
+    pub fn message(self) -> String {
+        locale::message(self.code(), &[])
+    }
+
+    /// Returns the likely cause of the warning.
+    pub fn cause(self) -> String {
+        locale::message(&format!("{}.cause", self.code()), &[])
+    }
 }